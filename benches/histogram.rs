@@ -25,5 +25,10 @@ fn atomic(c: &mut Criterion) {
     benchmark!("atomic_histogram", histogram, c);
 }
 
-criterion_group!(benches, histogram, atomic);
+fn sharded(c: &mut Criterion) {
+    let histogram = histogram::ShardedAtomicHistogram::new(7, 64, 16).unwrap();
+    benchmark!("sharded_atomic_histogram", histogram, c);
+}
+
+criterion_group!(benches, histogram, atomic, sharded);
 criterion_main!(benches);
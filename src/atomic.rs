@@ -1,4 +1,4 @@
-use crate::{Config, Error, Histogram};
+use crate::{Config, CounterPolicy, Error, Histogram, RangePolicy, Summary};
 use core::sync::atomic::{AtomicU64, Ordering};
 
 /// A histogram that uses atomic 64bit counters for each bucket.
@@ -9,6 +9,94 @@ use core::sync::atomic::{AtomicU64, Ordering};
 pub struct AtomicHistogram {
     config: Config,
     buckets: Box<[AtomicU64]>,
+    summary: Option<Box<AtomicSummary>>,
+    range_policy: RangePolicy,
+    overflow_count: AtomicU64,
+    counter_policy: CounterPolicy,
+}
+
+/// Atomic counterpart of [`Summary`].
+///
+/// The `u128` sum is split into two atomic halves, with the carry out of the
+/// low half added to the high half. A value recorded while the summary is
+/// being drained may be split across the two drains, but no part of the sum
+/// is lost.
+struct AtomicSummary {
+    min: AtomicU64,
+    max: AtomicU64,
+    sum_lo: AtomicU64,
+    sum_hi: AtomicU64,
+    count: AtomicU64,
+}
+
+impl AtomicSummary {
+    fn new() -> Self {
+        let summary = Summary::new();
+
+        Self {
+            min: AtomicU64::new(summary.min),
+            max: AtomicU64::new(summary.max),
+            sum_lo: AtomicU64::new(summary.sum as u64),
+            sum_hi: AtomicU64::new((summary.sum >> 64) as u64),
+            count: AtomicU64::new(summary.count),
+        }
+    }
+
+    fn add_sum(&self, sum: u128) {
+        let lo = sum as u64;
+        let carry = self
+            .sum_lo
+            .fetch_add(lo, Ordering::Relaxed)
+            .overflowing_add(lo)
+            .1;
+        let hi = ((sum >> 64) as u64).wrapping_add(carry as u64);
+        self.sum_hi.fetch_add(hi, Ordering::Relaxed);
+    }
+
+    fn record(&self, value: u64, count: u64) {
+        if count == 0 {
+            return;
+        }
+
+        self.min.fetch_min(value, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+        self.add_sum(value as u128 * count as u128);
+        self.count.fetch_add(count, Ordering::Relaxed);
+    }
+
+    fn load(&self) -> Summary {
+        Summary {
+            min: self.min.load(Ordering::Relaxed),
+            max: self.max.load(Ordering::Relaxed),
+            sum: (self.sum_hi.load(Ordering::Relaxed) as u128) << 64
+                | self.sum_lo.load(Ordering::Relaxed) as u128,
+            count: self.count.load(Ordering::Relaxed),
+        }
+    }
+
+    fn merge(&self, summary: &Summary) {
+        if summary.count == 0 {
+            return;
+        }
+
+        self.min.fetch_min(summary.min, Ordering::Relaxed);
+        self.max.fetch_max(summary.max, Ordering::Relaxed);
+        self.add_sum(summary.sum);
+        self.count.fetch_add(summary.count, Ordering::Relaxed);
+    }
+
+    #[cfg(target_has_atomic = "64")]
+    fn drain(&self) -> Summary {
+        let empty = Summary::new();
+
+        Summary {
+            min: self.min.swap(empty.min, Ordering::Relaxed),
+            max: self.max.swap(empty.max, Ordering::Relaxed),
+            sum: (self.sum_hi.swap(0, Ordering::Relaxed) as u128) << 64
+                | self.sum_lo.swap(0, Ordering::Relaxed) as u128,
+            count: self.count.swap(empty.count, Ordering::Relaxed),
+        }
+    }
 }
 
 impl AtomicHistogram {
@@ -28,18 +116,102 @@ impl AtomicHistogram {
         Self {
             config: *config,
             buckets: buckets.into(),
+            summary: None,
+            range_policy: RangePolicy::Error,
+            overflow_count: AtomicU64::new(0),
+            counter_policy: CounterPolicy::Wrapping,
         }
     }
 
+    /// Enables tracking of the exact minimum, maximum, sum, and count of the
+    /// recorded values. The summary is included in the snapshots returned by
+    /// [`load`](AtomicHistogram::load) and [`drain`](AtomicHistogram::drain).
+    ///
+    /// The summary fields are updated independently of the buckets, so a
+    /// snapshot taken while values are being recorded may observe a summary
+    /// that is slightly ahead of or behind the bucket counts.
+    pub fn with_summary(mut self) -> Self {
+        self.summary = Some(Box::new(AtomicSummary::new()));
+        self
+    }
+
+    /// Sets how values above the max value of the config are recorded. See
+    /// [`Histogram::with_range_policy`]. The policy and the overflow count are
+    /// included in the snapshots.
+    pub fn with_range_policy(mut self, policy: RangePolicy) -> Self {
+        self.range_policy = policy;
+        self
+    }
+
+    /// Returns how values above the max value of the config are recorded.
+    pub fn range_policy(&self) -> RangePolicy {
+        self.range_policy
+    }
+
+    /// Sets how a counter which would overflow is updated when a value is
+    /// recorded. See [`Histogram::with_counter_policy`]. The policy is
+    /// included in the snapshots.
+    ///
+    /// The saturating and checked policies update the counter with a
+    /// compare-and-swap loop, which is slower than the `fetch_add` used for
+    /// wrapping under contention.
+    pub fn with_counter_policy(mut self, policy: CounterPolicy) -> Self {
+        self.counter_policy = policy;
+        self
+    }
+
+    /// Returns how a counter which would overflow is updated when a value is
+    /// recorded.
+    pub fn counter_policy(&self) -> CounterPolicy {
+        self.counter_policy
+    }
+
+    /// Returns the count of values above the max value of the config which
+    /// were recorded with [`RangePolicy::Count`].
+    pub fn overflow_count(&self) -> u64 {
+        self.overflow_count.load(Ordering::Relaxed)
+    }
+
     /// Increment the bucket that contains the value by one.
     pub fn increment(&self, value: u64) -> Result<(), Error> {
         self.add(value, 1)
     }
 
-    /// Add `count` to the bucket that contains the `value`.
+    /// Add `count` to the bucket that contains the `value`. The counter is
+    /// updated according to the [`CounterPolicy`], and values above the max
+    /// value of the config are handled according to the [`RangePolicy`].
     pub fn add(&self, value: u64, count: u64) -> Result<(), Error> {
-        let index = self.config.value_to_index(value)?;
-        self.buckets[index].fetch_add(count, Ordering::Relaxed);
+        self.record(value, count, self.counter_policy)
+    }
+
+    /// Add `count` to the bucket that contains the `value`, returning
+    /// `Err(Error::Overflow)` and leaving the bucket unchanged if its counter
+    /// would overflow.
+    pub fn checked_add_value(&self, value: u64, count: u64) -> Result<(), Error> {
+        self.record(value, count, CounterPolicy::Checked)
+    }
+
+    /// Add `count` to the bucket that contains the `value`, stopping at
+    /// `u64::MAX` if its counter would overflow.
+    pub fn saturating_add_value(&self, value: u64, count: u64) -> Result<(), Error> {
+        self.record(value, count, CounterPolicy::Saturating)
+    }
+
+    /// Add `count` to the bucket that contains the `value`, wrapping around if
+    /// its counter would overflow.
+    pub fn wrapping_add_value(&self, value: u64, count: u64) -> Result<(), Error> {
+        self.record(value, count, CounterPolicy::Wrapping)
+    }
+
+    fn record(&self, value: u64, count: u64, policy: CounterPolicy) -> Result<(), Error> {
+        let counter = match self.range_policy.index(&self.config, value)? {
+            Some(index) => &self.buckets[index],
+            None => &self.overflow_count,
+        };
+        policy.fetch_add(counter, count)?;
+        if let Some(summary) = &self.summary {
+            summary.record(value, count);
+        }
         Ok(())
     }
 
@@ -59,6 +231,13 @@ impl AtomicHistogram {
         Histogram {
             config: self.config,
             buckets: buckets.into(),
+            summary: self
+                .summary
+                .as_ref()
+                .map(|summary| Box::new(summary.drain())),
+            range_policy: self.range_policy,
+            overflow_count: self.overflow_count.swap(0, Ordering::Relaxed),
+            counter_policy: self.counter_policy,
         }
     }
 
@@ -67,6 +246,46 @@ impl AtomicHistogram {
         self.config
     }
 
+    /// Returns whether summary tracking is enabled.
+    pub(crate) fn has_summary(&self) -> bool {
+        self.summary.is_some()
+    }
+
+    /// Adds the counts of the buckets at `indices` from a histogram with the
+    /// same config, along with its overflow count and its summary if both
+    /// histograms track one. The counters are updated according to the
+    /// [`CounterPolicy`], and the first error is returned after every other
+    /// counter has been updated.
+    ///
+    /// If `wrap_on_overflow` is set, a count which the policy rejects is added
+    /// with wrapping arithmetic instead of being discarded, and the error is
+    /// still returned.
+    pub(crate) fn add_buckets(
+        &self,
+        histogram: &Histogram,
+        indices: &[usize],
+        wrap_on_overflow: bool,
+    ) -> Result<(), Error> {
+        let add = |counter: &AtomicU64, count| {
+            let added = self.counter_policy.fetch_add(counter, count);
+            if added.is_err() && wrap_on_overflow {
+                counter.fetch_add(count, Ordering::Relaxed);
+            }
+            added
+        };
+        let mut result = Ok(());
+
+        for index in indices {
+            result = result.and(add(&self.buckets[*index], histogram.buckets[*index]));
+        }
+        result = result.and(add(&self.overflow_count, histogram.overflow_count));
+        if let (Some(this), Some(other)) = (&self.summary, &histogram.summary) {
+            this.merge(other);
+        }
+
+        result
+    }
+
     /// Read the bucket values into a new `Histogram`
     pub fn load(&self) -> Histogram {
         let buckets: Vec<u64> = self
@@ -78,6 +297,13 @@ impl AtomicHistogram {
         Histogram {
             config: self.config,
             buckets: buckets.into(),
+            summary: self
+                .summary
+                .as_ref()
+                .map(|summary| Box::new(summary.load())),
+            range_policy: self.range_policy,
+            overflow_count: self.overflow_count.load(Ordering::Relaxed),
+            counter_policy: self.counter_policy,
         }
     }
 }
@@ -97,7 +323,7 @@ mod tests {
     #[cfg(target_pointer_width = "64")]
     #[test]
     fn size() {
-        assert_eq!(std::mem::size_of::<AtomicHistogram>(), 48);
+        assert_eq!(std::mem::size_of::<AtomicHistogram>(), 72);
     }
 
     #[cfg(target_has_atomic = "64")]
@@ -128,6 +354,87 @@ mod tests {
         );
     }
 
+    #[cfg(target_has_atomic = "64")]
+    #[test]
+    /// Tests that the summary is carried into snapshots and reset by drain
+    fn summary() {
+        let histogram = AtomicHistogram::new(7, 64).unwrap();
+        assert_eq!(histogram.load().summary(), None);
+
+        let histogram = AtomicHistogram::new(7, 64).unwrap().with_summary();
+        for i in 1..=100 {
+            let _ = histogram.increment(i);
+        }
+        let summary = histogram.load().summary().unwrap();
+        assert_eq!(summary.min(), Some(1));
+        assert_eq!(summary.max(), Some(100));
+        assert_eq!(summary.sum(), 5050);
+        assert_eq!(summary.count(), 100);
+
+        assert_eq!(histogram.drain().summary(), Some(summary));
+        assert_eq!(histogram.load().summary().unwrap().min(), None);
+
+        // the sum carries into its high half
+        histogram.add(u64::MAX, 3).unwrap();
+        histogram.add(u64::MAX, 1).unwrap();
+        let summary = histogram.load().summary().unwrap();
+        assert_eq!(summary.sum(), 4 * u64::MAX as u128);
+    }
+
+    #[cfg(target_has_atomic = "64")]
+    #[test]
+    /// Tests that the overflow count is carried into snapshots and reset by
+    /// drain
+    fn range_policy() {
+        let histogram = AtomicHistogram::new(4, 10).unwrap();
+        assert_eq!(histogram.increment(1024), Err(Error::OutOfRange));
+
+        let histogram = AtomicHistogram::new(4, 10)
+            .unwrap()
+            .with_range_policy(RangePolicy::Count);
+        histogram.increment(5).unwrap();
+        histogram.add(1024, 3).unwrap();
+        assert_eq!(histogram.overflow_count(), 3);
+
+        let snapshot = histogram.load();
+        assert_eq!(snapshot.range_policy(), RangePolicy::Count);
+        assert_eq!(snapshot.overflow_count(), 3);
+        assert_eq!(histogram.drain(), snapshot);
+        assert_eq!(histogram.overflow_count(), 0);
+
+        let histogram = AtomicHistogram::new(4, 10)
+            .unwrap()
+            .with_range_policy(RangePolicy::Clamp);
+        histogram.increment(u64::MAX).unwrap();
+        assert_eq!(histogram.load().as_slice().last(), Some(&1));
+    }
+
+    #[test]
+    /// Tests that the counter policy is applied atomically
+    fn counter_policy() {
+        let histogram = AtomicHistogram::new(4, 10)
+            .unwrap()
+            .with_counter_policy(CounterPolicy::Checked);
+        histogram.add(5, u64::MAX - 1).unwrap();
+        histogram.increment(5).unwrap();
+        assert_eq!(histogram.increment(5), Err(Error::Overflow));
+        assert_eq!(histogram.saturating_add_value(5, 2), Ok(()));
+        assert_eq!(histogram.load().as_slice()[5], u64::MAX);
+        assert_eq!(histogram.load().counter_policy(), CounterPolicy::Checked);
+
+        histogram.wrapping_add_value(5, 2).unwrap();
+        assert_eq!(histogram.load().as_slice()[5], 1);
+
+        let histogram = AtomicHistogram::new(4, 10)
+            .unwrap()
+            .with_counter_policy(CounterPolicy::Saturating)
+            .with_range_policy(RangePolicy::Count);
+        histogram.add(1024, u64::MAX).unwrap();
+        histogram.add(1024, 1).unwrap();
+        assert_eq!(histogram.overflow_count(), u64::MAX);
+        assert_eq!(histogram.checked_add_value(1024, 1), Err(Error::Overflow));
+    }
+
     #[test]
     // Tests percentiles
     fn percentiles() {
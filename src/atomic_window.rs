@@ -0,0 +1,176 @@
+use crate::{AtomicHistogram, Config, Error, Histogram, WindowedHistogram};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// A sliding window histogram which allows concurrent recording.
+///
+/// Writers record into an [`AtomicHistogram`] for the current interval, so
+/// recording is a single relaxed `fetch_add` and never blocks. A reporter
+/// periodically calls [`rotate`](AtomicWindowedHistogram::rotate), which
+/// drains the current interval and merges it into a [`WindowedHistogram`] of
+/// the most recent completed intervals. Only the reporter and readers take
+/// the lock protecting the window.
+pub struct AtomicWindowedHistogram {
+    current: AtomicHistogram,
+    window: Mutex<WindowedHistogram>,
+}
+
+impl AtomicWindowedHistogram {
+    /// Construct a new atomic windowed histogram from the provided parameters
+    /// and number of completed intervals to retain. See the documentation for
+    /// [`crate::Config`] to understand their meaning.
+    ///
+    /// Returns `Err(Error::IncompatibleParameters)` if `intervals` is zero.
+    pub fn new(grouping_power: u8, max_value_power: u8, intervals: usize) -> Result<Self, Error> {
+        let config = Config::new(grouping_power, max_value_power)?;
+
+        Self::with_config(&config, intervals)
+    }
+
+    /// Creates a new atomic windowed histogram with the provided number of
+    /// completed intervals to retain using a provided [`crate::Config`].
+    ///
+    /// Returns `Err(Error::IncompatibleParameters)` if `intervals` is zero.
+    pub fn with_config(config: &Config, intervals: usize) -> Result<Self, Error> {
+        Ok(Self {
+            current: AtomicHistogram::with_config(config),
+            window: Mutex::new(WindowedHistogram::with_config(config, intervals)?),
+        })
+    }
+
+    /// Returns the bucket configuration of the histogram.
+    pub fn config(&self) -> Config {
+        self.current.config()
+    }
+
+    /// Increment the bucket that contains the value in the current interval
+    /// by one.
+    pub fn increment(&self, value: u64) -> Result<(), Error> {
+        self.current.add(value, 1)
+    }
+
+    /// Add `count` to the bucket that contains the `value` in the current
+    /// interval.
+    pub fn add(&self, value: u64, count: u64) -> Result<(), Error> {
+        self.current.add(value, count)
+    }
+
+    // NOTE: once stabilized, `target_has_atomic_load_store` is more correct. https://github.com/rust-lang/rust/issues/94039
+    #[cfg(target_has_atomic = "64")]
+    /// Completes the current interval by draining it into the window,
+    /// discarding the oldest completed interval.
+    ///
+    /// This is intended to be called periodically from a single reporting
+    /// thread. Values recorded concurrently with the drain are attributed to
+    /// either the completed or the next interval.
+    pub fn rotate(&self) {
+        let interval = self.current.drain();
+        let mut window = self.window();
+        window.tick();
+        // the configs always match, so this cannot fail
+        let _ = window.add_histogram(&interval);
+    }
+
+    /// Returns a histogram covering all the completed intervals in the
+    /// window.
+    pub fn load(&self) -> Histogram {
+        self.window().aggregate().clone()
+    }
+
+    /// Returns a histogram covering the most recent `intervals` completed
+    /// intervals.
+    ///
+    /// Returns `Err(Error::IncompatibleParameters)` if `intervals` is zero or
+    /// larger than the number of intervals in the window.
+    pub fn recent(&self, intervals: usize) -> Result<Histogram, Error> {
+        self.window().recent(intervals)
+    }
+
+    /// Returns a snapshot of the current, incomplete interval.
+    pub fn load_current(&self) -> Histogram {
+        self.current.load()
+    }
+
+    fn window(&self) -> MutexGuard<'_, WindowedHistogram> {
+        // the window is never left in an inconsistent state, so it is safe to
+        // continue using it if another thread panicked while holding the lock
+        self.window.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl std::fmt::Debug for AtomicWindowedHistogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AtomicWindowedHistogram")
+            .field("config", &self.config())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[cfg(target_has_atomic = "64")]
+    #[test]
+    // Tests that completed intervals are merged and expire
+    fn rotate() {
+        let histogram = AtomicWindowedHistogram::new(7, 64, 2).unwrap();
+        histogram.increment(1).unwrap();
+        assert_eq!(histogram.load().percentile(0.5), Ok(None));
+        assert_eq!(
+            histogram
+                .load_current()
+                .percentile(0.5)
+                .unwrap()
+                .unwrap()
+                .end(),
+            1
+        );
+
+        histogram.rotate();
+        histogram.increment(2).unwrap();
+        histogram.rotate();
+
+        let window = histogram.load();
+        assert_eq!(window.percentile(0.0).map(|b| b.unwrap().end()), Ok(1));
+        assert_eq!(window.percentile(1.0).map(|b| b.unwrap().end()), Ok(2));
+        let recent = histogram.recent(1).unwrap();
+        assert_eq!(recent.percentile(0.0).map(|b| b.unwrap().end()), Ok(2));
+
+        histogram.rotate();
+        let window = histogram.load();
+        assert_eq!(window.percentile(0.0).map(|b| b.unwrap().end()), Ok(2));
+
+        histogram.rotate();
+        assert_eq!(histogram.load().percentile(0.5), Ok(None));
+    }
+
+    #[cfg(target_has_atomic = "64")]
+    #[test]
+    // Tests recording from multiple threads while rotating
+    fn concurrent() {
+        let histogram = Arc::new(AtomicWindowedHistogram::new(7, 64, 4).unwrap());
+
+        let writers: Vec<_> = (0..4)
+            .map(|_| {
+                let histogram = histogram.clone();
+                std::thread::spawn(move || {
+                    for value in 0..1000 {
+                        histogram.increment(value).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for _ in 0..3 {
+            histogram.rotate();
+        }
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        histogram.rotate();
+
+        let total: u64 = histogram.load().as_slice().iter().sum();
+        assert_eq!(total, 4000);
+    }
+}
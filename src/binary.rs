@@ -0,0 +1,501 @@
+//! A compact, versioned binary encoding for [`SparseHistogram`].
+//!
+//! The encoding is:
+//! - the magic bytes `HIST`
+//! - the format version, currently `1`
+//! - the grouping power and max value power
+//! - a flags byte, where bit 0 indicates a summary is present, bit 1
+//!   indicates a range policy is present, and bit 2 indicates a counter
+//!   policy is present
+//! - the number of non-zero buckets
+//! - the index of each bucket, encoded as the gap from the previous index
+//! - the count of each bucket
+//! - the minimum, maximum, sum, and count of the summary, if present, where
+//!   the sum may be up to 128 bits
+//! - the range policy (`0` for error, `1` for clamp, `2` for count) and the
+//!   overflow count, if present
+//! - the counter policy (`0` for wrapping, `1` for saturating, `2` for
+//!   checked), if present
+//!
+//! All integers after the flags are unsigned LEB128 varints.
+
+use crate::{Config, CounterPolicy, Error, RangePolicy, SparseHistogram, Summary};
+use std::io::{self, Read, Write};
+
+const MAGIC: [u8; 4] = *b"HIST";
+const VERSION: u8 = 1;
+const FLAG_SUMMARY: u8 = 1;
+const FLAG_RANGE: u8 = 2;
+const FLAG_COUNTER: u8 = 4;
+
+/// The most buckets reserved up front when decoding. The length is read from
+/// the input, so larger histograms grow as their buckets are read instead.
+const MAX_RESERVED: usize = 4096;
+
+/// Writes an unsigned LEB128 varint.
+pub(crate) fn write_varint(buf: &mut Vec<u8>, value: u64) {
+    write_wide_varint(buf, value as u128);
+}
+
+/// Writes an unsigned LEB128 varint of up to 128 bits.
+fn write_wide_varint(buf: &mut Vec<u8>, mut value: u128) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Reads an unsigned LEB128 varint, rejecting values which overflow a `u64`.
+pub(crate) fn read_varint<R: Read>(reader: &mut R) -> io::Result<u64> {
+    read_bits(reader, u64::BITS).map(|value| value as u64)
+}
+
+/// Reads an unsigned LEB128 varint, rejecting values which overflow a `u128`.
+fn read_wide_varint<R: Read>(reader: &mut R) -> io::Result<u128> {
+    read_bits(reader, u128::BITS)
+}
+
+/// Reads an unsigned LEB128 varint, rejecting values wider than `bits`.
+fn read_bits<R: Read>(reader: &mut R, bits: u32) -> io::Result<u128> {
+    let mut value = 0;
+
+    for shift in (0..bits).step_by(7) {
+        let byte = read_u8(reader)?;
+        // the last byte may only hold the remaining bits, with no continuation
+        if shift + 7 > bits && byte >> (bits - shift) != 0 {
+            break;
+        }
+        value |= ((byte & 0x7f) as u128) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(invalid_data(Error::InvalidEncoding))
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn invalid_data(error: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Recovers the histogram error from an error returned while decoding.
+pub(crate) fn to_error(error: io::Error) -> Error {
+    error
+        .into_inner()
+        .and_then(|e| e.downcast::<Error>().ok())
+        .map(|e| *e)
+        .unwrap_or(Error::InvalidEncoding)
+}
+
+fn encode(histogram: &SparseHistogram) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(8 + 3 * histogram.index.len());
+
+    buf.extend_from_slice(&MAGIC);
+    buf.push(VERSION);
+    buf.push(histogram.config.grouping_power());
+    buf.push(histogram.config.max_value_power());
+    // the policies are omitted when they are the default, so that the
+    // encoding of such histograms is unchanged
+    let range = !histogram.range_policy.is_default() || histogram.overflow_count != 0;
+    let counter = histogram.counter_policy != CounterPolicy::Wrapping;
+    let mut flags = 0;
+    if histogram.summary.is_some() {
+        flags |= FLAG_SUMMARY;
+    }
+    if range {
+        flags |= FLAG_RANGE;
+    }
+    if counter {
+        flags |= FLAG_COUNTER;
+    }
+    buf.push(flags);
+
+    write_varint(&mut buf, histogram.index.len() as u64);
+    let mut next = 0;
+    for index in &histogram.index {
+        write_varint(&mut buf, (index - next) as u64);
+        next = index + 1;
+    }
+    for count in &histogram.count {
+        write_varint(&mut buf, *count);
+    }
+
+    if let Some(summary) = histogram.summary {
+        write_varint(&mut buf, summary.min);
+        write_varint(&mut buf, summary.max);
+        write_wide_varint(&mut buf, summary.sum);
+        write_varint(&mut buf, summary.count);
+    }
+
+    if range {
+        let policy = match histogram.range_policy {
+            RangePolicy::Error => 0,
+            RangePolicy::Clamp => 1,
+            RangePolicy::Count => 2,
+        };
+        write_varint(&mut buf, policy);
+        write_varint(&mut buf, histogram.overflow_count);
+    }
+
+    if counter {
+        let policy = match histogram.counter_policy {
+            CounterPolicy::Wrapping => 0,
+            CounterPolicy::Saturating => 1,
+            CounterPolicy::Checked => 2,
+        };
+        write_varint(&mut buf, policy);
+    }
+
+    buf
+}
+
+/// The fields which precede the buckets.
+pub(crate) struct Header {
+    pub(crate) config: Config,
+    pub(crate) len: usize,
+    pub(crate) summary: bool,
+    pub(crate) range: bool,
+    pub(crate) counter: bool,
+}
+
+/// Reads the header, checking the format version and the config.
+pub(crate) fn read_header<R: Read>(reader: &mut R) -> io::Result<Header> {
+    let mut header = [0; 8];
+    reader.read_exact(&mut header)?;
+
+    if header[..4] != MAGIC
+        || header[4] != VERSION
+        || header[7] & !(FLAG_SUMMARY | FLAG_RANGE | FLAG_COUNTER) != 0
+    {
+        return Err(invalid_data(Error::InvalidEncoding));
+    }
+    let config = Config::new(header[5], header[6]).map_err(invalid_data)?;
+
+    // the indices are strictly ascending, so there cannot be more buckets
+    // than the config has
+    let len = read_varint(reader)?;
+    if len > config.total_buckets() as u64 {
+        return Err(invalid_data(Error::InvalidEncoding));
+    }
+
+    Ok(Header {
+        config,
+        len: len as usize,
+        summary: header[7] & FLAG_SUMMARY != 0,
+        range: header[7] & FLAG_RANGE != 0,
+        counter: header[7] & FLAG_COUNTER != 0,
+    })
+}
+
+/// Reads the next index from its gap to the previous index, where `next` is
+/// one past the previous index.
+pub(crate) fn read_index<R: Read>(reader: &mut R, next: u64) -> io::Result<u32> {
+    read_varint(reader)?
+        .checked_add(next)
+        .filter(|i| *i <= u32::MAX as u64)
+        .map(|i| i as u32)
+        .ok_or(invalid_data(Error::OutOfRange))
+}
+
+pub(crate) fn read_summary<R: Read>(reader: &mut R) -> io::Result<Summary> {
+    Ok(Summary {
+        min: read_varint(reader)?,
+        max: read_varint(reader)?,
+        sum: read_wide_varint(reader)?,
+        count: read_varint(reader)?,
+    })
+}
+
+/// Reads the range policy and the overflow count.
+pub(crate) fn read_range<R: Read>(reader: &mut R) -> io::Result<(RangePolicy, u64)> {
+    let policy = match read_varint(reader)? {
+        0 => RangePolicy::Error,
+        1 => RangePolicy::Clamp,
+        2 => RangePolicy::Count,
+        _ => return Err(invalid_data(Error::InvalidEncoding)),
+    };
+
+    Ok((policy, read_varint(reader)?))
+}
+
+/// Reads the counter policy.
+pub(crate) fn read_counter<R: Read>(reader: &mut R) -> io::Result<CounterPolicy> {
+    match read_varint(reader)? {
+        0 => Ok(CounterPolicy::Wrapping),
+        1 => Ok(CounterPolicy::Saturating),
+        2 => Ok(CounterPolicy::Checked),
+        _ => Err(invalid_data(Error::InvalidEncoding)),
+    }
+}
+
+fn decode<R: Read>(reader: &mut R) -> io::Result<SparseHistogram> {
+    let Header {
+        config,
+        len,
+        summary,
+        range,
+        counter,
+    } = read_header(reader)?;
+
+    let mut index = Vec::with_capacity(len.min(MAX_RESERVED));
+    let mut next = 0;
+    for _ in 0..len {
+        let i = read_index(reader, next)?;
+        index.push(i);
+        next = i as u64 + 1;
+    }
+
+    let mut count = Vec::with_capacity(len.min(MAX_RESERVED));
+    for _ in 0..len {
+        count.push(read_varint(reader)?);
+    }
+
+    let summary = if summary {
+        Some(read_summary(reader)?)
+    } else {
+        None
+    };
+
+    let (range_policy, overflow_count) = if range {
+        read_range(reader)?
+    } else {
+        (RangePolicy::Error, 0)
+    };
+    let counter_policy = if counter {
+        read_counter(reader)?
+    } else {
+        CounterPolicy::Wrapping
+    };
+
+    let mut histogram = SparseHistogram::from_parts(config, index, count).map_err(invalid_data)?;
+    histogram.summary = summary;
+    histogram.range_policy = range_policy;
+    histogram.overflow_count = overflow_count;
+    histogram.counter_policy = counter_policy;
+    Ok(histogram)
+}
+
+impl SparseHistogram {
+    /// Encodes the histogram in a compact binary format. See
+    /// [`from_bytes`](SparseHistogram::from_bytes) for decoding.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(self)
+    }
+
+    /// Decodes a histogram from the binary format produced by
+    /// [`to_bytes`](SparseHistogram::to_bytes).
+    ///
+    /// The decoded parts are checked as in
+    /// [`from_parts`](SparseHistogram::from_parts), and its errors are
+    /// returned as is. Returns `Err(Error::InvalidEncoding)` if the bytes are
+    /// truncated, have trailing data, or are not in a supported version of
+    /// the format.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut buf = bytes;
+        let histogram = decode(&mut buf).map_err(to_error)?;
+
+        if !buf.is_empty() {
+            return Err(Error::InvalidEncoding);
+        }
+
+        Ok(histogram)
+    }
+
+    /// Writes the histogram to the writer in the binary format produced by
+    /// [`to_bytes`](SparseHistogram::to_bytes).
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&encode(self))
+    }
+
+    /// Reads a histogram in the binary format produced by
+    /// [`to_bytes`](SparseHistogram::to_bytes) from the reader. Reading stops
+    /// at the end of the histogram, so several histograms can be read from a
+    /// single stream. The reader should be buffered, as it is read one byte at
+    /// a time.
+    ///
+    /// Decoding errors are returned with [`io::ErrorKind::InvalidData`] and
+    /// wrap the [`Error`]. See [`from_bytes`](SparseHistogram::from_bytes).
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<Self> {
+        decode(&mut reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Histogram;
+
+    fn histogram() -> SparseHistogram {
+        let mut histogram = Histogram::new(7, 64).unwrap();
+        for value in (1..100_000).step_by(37) {
+            histogram.increment(value).unwrap();
+        }
+        histogram.add(u64::MAX, 1 << 40).unwrap();
+        SparseHistogram::from(&histogram)
+    }
+
+    #[test]
+    fn varint() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, value);
+            assert_eq!(read_varint(&mut buf.as_slice()).unwrap(), value);
+        }
+
+        // overflows a u64
+        let buf = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02];
+        assert!(read_varint(&mut buf.as_slice()).is_err());
+        assert_eq!(
+            read_wide_varint(&mut buf.as_slice()).unwrap(),
+            (1 << 64) + (1 << 63) - 1
+        );
+
+        let mut buf = Vec::new();
+        write_wide_varint(&mut buf, u128::MAX);
+        assert_eq!(read_wide_varint(&mut buf.as_slice()).unwrap(), u128::MAX);
+
+        // overflows a u128
+        *buf.last_mut().unwrap() = 0x04;
+        assert!(read_wide_varint(&mut buf.as_slice()).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let histogram = histogram();
+        let bytes = histogram.to_bytes();
+        assert_eq!(SparseHistogram::from_bytes(&bytes), Ok(histogram.clone()));
+
+        // smaller than the raw columns
+        assert!(bytes.len() < histogram.index().len() * 4);
+
+        let mut with_summary = Histogram::new(4, 32).unwrap().with_summary();
+        with_summary.increment(42).unwrap();
+        with_summary.add(u32::MAX as u64, u64::MAX).unwrap();
+        let with_summary = SparseHistogram::from(&with_summary);
+        let bytes = with_summary.to_bytes();
+        let decoded = SparseHistogram::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.summary(), with_summary.summary());
+
+        let empty = SparseHistogram::new(7, 64).unwrap();
+        assert_eq!(empty.to_bytes().len(), 9);
+        assert_eq!(SparseHistogram::from_bytes(&empty.to_bytes()), Ok(empty));
+    }
+
+    #[test]
+    fn stream() {
+        let histogram = histogram();
+
+        let mut buf = Vec::new();
+        histogram.write_to(&mut buf).unwrap();
+        SparseHistogram::new(2, 10)
+            .unwrap()
+            .write_to(&mut buf)
+            .unwrap();
+
+        let mut reader = io::BufReader::new(buf.as_slice());
+        assert_eq!(SparseHistogram::read_from(&mut reader).unwrap(), histogram);
+        let next = SparseHistogram::read_from(&mut reader).unwrap();
+        assert_eq!(next.config(), Config::new(2, 10).unwrap());
+        assert_eq!(
+            SparseHistogram::read_from(&mut reader).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        let bytes = histogram().to_bytes();
+
+        assert_eq!(
+            SparseHistogram::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(Error::InvalidEncoding)
+        );
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            SparseHistogram::from_bytes(&trailing),
+            Err(Error::InvalidEncoding)
+        );
+        let mut version = bytes.clone();
+        version[4] = 2;
+        assert_eq!(
+            SparseHistogram::from_bytes(&version),
+            Err(Error::InvalidEncoding)
+        );
+
+        // checked like the config
+        let mut config = bytes.clone();
+        config[6] = 65;
+        assert_eq!(
+            SparseHistogram::from_bytes(&config),
+            Err(Error::MaxPowerTooHigh)
+        );
+
+        // checked like from_parts
+        let out_of_range = [b'H', b'I', b'S', b'T', 1, 2, 4, 0, 1, 12, 1];
+        assert_eq!(
+            SparseHistogram::from_bytes(&out_of_range),
+            Err(Error::OutOfRange)
+        );
+        let zero_count = [b'H', b'I', b'S', b'T', 1, 2, 4, 0, 1, 3, 0];
+        assert_eq!(
+            SparseHistogram::from_bytes(&zero_count),
+            Err(Error::IncompatibleParameters)
+        );
+
+        let error = SparseHistogram::read_from(&out_of_range[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let policy = [b'H', b'I', b'S', b'T', 1, 2, 4, FLAG_RANGE, 0, 3, 0];
+        assert_eq!(
+            SparseHistogram::from_bytes(&policy),
+            Err(Error::InvalidEncoding)
+        );
+
+        // a length which is allowed by the config but is not followed by any
+        // buckets is rejected without reserving space for all of them
+        let config = Config::new(26, 64).unwrap();
+        let mut huge = vec![b'H', b'I', b'S', b'T', 1, 26, 64, 0];
+        write_varint(&mut huge, config.total_buckets() as u64);
+        assert_eq!(
+            SparseHistogram::from_bytes(&huge),
+            Err(Error::InvalidEncoding)
+        );
+    }
+
+    #[test]
+    fn range_policy() {
+        let mut histogram = Histogram::new(4, 10)
+            .unwrap()
+            .with_range_policy(RangePolicy::Count);
+        histogram.increment(5).unwrap();
+        histogram.add(1024, 3).unwrap();
+        let sparse = SparseHistogram::from(&histogram);
+
+        let bytes = sparse.to_bytes();
+        assert_eq!(bytes[7], FLAG_RANGE);
+        assert_eq!(SparseHistogram::from_bytes(&bytes), Ok(sparse));
+
+        let view = crate::SparseHistogramView::new(&bytes).unwrap();
+        assert_eq!(view.range_policy(), RangePolicy::Count);
+        assert_eq!(view.overflow_count(), 3);
+
+        // the default policy is not encoded
+        let sparse = SparseHistogram::from(&Histogram::new(4, 10).unwrap());
+        assert_eq!(sparse.to_bytes()[7], 0);
+
+        let sparse = sparse.with_counter_policy(CounterPolicy::Checked);
+        let bytes = sparse.to_bytes();
+        assert_eq!(bytes[7], FLAG_COUNTER);
+        assert_eq!(SparseHistogram::from_bytes(&bytes), Ok(sparse));
+        let view = crate::SparseHistogramView::new(&bytes).unwrap();
+        assert_eq!(view.counter_policy(), CounterPolicy::Checked);
+    }
+}
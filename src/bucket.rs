@@ -1,31 +1,89 @@
+use crate::Error;
 use core::ops::RangeInclusive;
 
 /// A bucket represents a quantized range of values and a count of observations
 /// that fall into that range.
+///
+/// The values are `u64` for most histograms, and `i64` for a
+/// [`SignedHistogram`](crate::SignedHistogram).
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Bucket {
+pub struct Bucket<T = u64> {
     pub(crate) count: u64,
-    pub(crate) range: RangeInclusive<u64>,
+    pub(crate) range: RangeInclusive<T>,
 }
 
-impl Bucket {
+impl<T: Copy> Bucket<T> {
     /// Returns the number of observations within the bucket's range.
     pub fn count(&self) -> u64 {
         self.count
     }
 
     /// Returns the range for the bucket.
-    pub fn range(&self) -> RangeInclusive<u64> {
+    pub fn range(&self) -> RangeInclusive<T> {
         self.range.clone()
     }
 
     /// Returns the inclusive lower bound for the bucket.
-    pub fn start(&self) -> u64 {
+    pub fn start(&self) -> T {
         *self.range.start()
     }
 
     /// Returns the inclusive upper bound for the bucket.
-    pub fn end(&self) -> u64 {
+    pub fn end(&self) -> T {
         *self.range.end()
     }
 }
+
+/// The buckets containing each of a collection of percentiles.
+pub(crate) type Percentiles<T> = Vec<(f64, Bucket<T>)>;
+
+/// Finds the buckets containing the percentiles, scanning the buckets in
+/// ascending order of their values. The results are sorted by the percentile.
+/// Returns `None` if the histogram is empty.
+pub(crate) fn percentiles<T: Copy>(
+    buckets: impl Iterator<Item = Bucket<T>>,
+    total_count: u128,
+    percentiles: &[f64],
+) -> Result<Option<Percentiles<T>>, Error> {
+    // validate all the percentiles
+    for percentile in percentiles {
+        if !(0.0..=1.0).contains(percentile) {
+            return Err(Error::InvalidPercentile);
+        }
+    }
+
+    // empty histogram, no percentiles available
+    if total_count == 0 {
+        return Ok(None);
+    }
+
+    // sort the requested percentiles so we can find them in a single pass
+    let mut percentiles = percentiles.to_vec();
+    percentiles.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let mut buckets = buckets.filter(|bucket| bucket.count() != 0);
+    let mut bucket = buckets.next().unwrap();
+    let mut partial_sum = bucket.count() as u128;
+
+    let result = percentiles
+        .iter()
+        .filter_map(|percentile| {
+            // For 0.0 percentile (min) we need to report the first bucket
+            // with a non-zero count.
+            let count = std::cmp::max(1, (percentile * total_count as f64).ceil() as u128);
+
+            loop {
+                // found the matching bucket for this percentile
+                if partial_sum >= count {
+                    return Some((*percentile, bucket.clone()));
+                }
+
+                // otherwise, move to the next bucket until we run out
+                bucket = buckets.next()?;
+                partial_sum += bucket.count() as u128;
+            }
+        })
+        .collect();
+
+    Ok(Some(result))
+}
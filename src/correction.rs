@@ -0,0 +1,366 @@
+//! Correction for coordinated omission, where a system which stalls delays the
+//! requests which would have been measured during the stall, so that only one
+//! long latency is recorded instead of many.
+//!
+//! As in HdrHistogram, a value which is longer than the expected interval
+//! between measurements is corrected by also recording the values which the
+//! delayed measurements would have seen: `value - interval`,
+//! `value - 2 * interval`, and so on, down to the interval.
+
+use crate::{Config, Error, Histogram, SparseHistogram, Summary};
+use std::collections::BTreeMap;
+
+/// A run of synthetic values which fall into the same bucket.
+struct Backfill {
+    /// The bucket index, or `None` for the values above the range of the
+    /// config.
+    index: Option<usize>,
+    /// The number of synthetic values.
+    count: u64,
+    /// The largest and smallest synthetic values.
+    max: u64,
+    min: u64,
+}
+
+impl Backfill {
+    /// Returns the summary of recording each synthetic value `count` times.
+    fn summary(&self, count: u64) -> Summary {
+        // the values are an arithmetic sequence, whose sum fits in a u128 but
+        // wraps like the summary does once multiplied by the count
+        let step = ((self.max - self.min) / (self.count - 1).max(1)) as u128;
+        let n = self.count as u128;
+        let sum = n * self.min as u128 + n * (n - 1) / 2 * step;
+
+        Summary {
+            min: self.min,
+            max: self.max,
+            sum: sum.wrapping_mul(count as u128),
+            count: self.count.wrapping_mul(count),
+        }
+    }
+}
+
+/// Returns the synthetic values for a value with the expected interval,
+/// grouped by bucket from the largest to the smallest. The values above the
+/// range of the config are grouped together.
+fn backfill(config: &Config, value: u64, interval: u64) -> impl Iterator<Item = Backfill> + '_ {
+    // the synthetic values are `value - k * interval` for `1 <= k <= last`
+    let last = value.checked_div(interval).unwrap_or(0).saturating_sub(1);
+    let mut k = 1;
+
+    core::iter::from_fn(move || {
+        if k > last {
+            return None;
+        }
+
+        let max = value - k * interval;
+        let (index, lower) = match config.value_to_index(max) {
+            Ok(index) => (Some(index), config.index_to_lower_bound(index)),
+            // the max value is below `u64::MAX` if there are values above it
+            Err(_) => (
+                None,
+                config.index_to_upper_bound(config.total_buckets() - 1) + 1,
+            ),
+        };
+
+        // the remaining values in this bucket
+        let end = last.min((value - lower) / interval);
+        let backfill = Backfill {
+            index,
+            count: end - k + 1,
+            max,
+            min: value - end * interval,
+        };
+        k = end + 1;
+
+        Some(backfill)
+    })
+}
+
+impl Histogram {
+    /// Increment the counter for the bucket corresponding to the provided
+    /// value by one, correcting for coordinated omission. See
+    /// [`add_with_expected_interval`](Histogram::add_with_expected_interval).
+    pub fn increment_with_expected_interval(
+        &mut self,
+        value: u64,
+        expected_interval: u64,
+    ) -> Result<(), Error> {
+        self.add_with_expected_interval(value, 1, expected_interval)
+    }
+
+    /// Add some count to the counter for the bucket corresponding to the
+    /// provided value, correcting for coordinated omission.
+    ///
+    /// If the value is larger than the `expected_interval` between
+    /// measurements, the values `value - k * expected_interval` which are at
+    /// least the interval are also recorded with the same count, as the
+    /// measurements which were delayed would have seen them. This matches
+    /// `recordValueWithExpectedInterval` in HdrHistogram. An interval of zero
+    /// disables the correction.
+    ///
+    /// The counters are updated according to the [`CounterPolicy`], and the
+    /// summary includes the synthetic values. If the policy is
+    /// [`CounterPolicy::Checked`] and a counter would overflow, an error is
+    /// returned and the values recorded before it are kept. Synthetic values
+    /// above the max value of the config are handled according to the
+    /// [`RangePolicy`].
+    ///
+    /// [`CounterPolicy`]: crate::CounterPolicy
+    /// [`CounterPolicy::Checked`]: crate::CounterPolicy::Checked
+    /// [`RangePolicy`]: crate::RangePolicy
+    pub fn add_with_expected_interval(
+        &mut self,
+        value: u64,
+        count: u64,
+        expected_interval: u64,
+    ) -> Result<(), Error> {
+        self.add(value, count)?;
+
+        let policy = self.counter_policy;
+        for backfill in backfill(&self.config, value, expected_interval) {
+            // the value was recorded, so the policy accepts the values above
+            // the range
+            let index = match backfill.index {
+                Some(index) => Some(index),
+                None => self.range_policy.index(&self.config, backfill.max)?,
+            };
+            let counter = match index {
+                Some(index) => &mut self.buckets[index],
+                None => &mut self.overflow_count,
+            };
+            *counter = policy
+                .mul(backfill.count, count)
+                .and_then(|n| policy.add(*counter, n))?;
+            if let Some(summary) = &mut self.summary {
+                **summary = summary.wrapping_add(&backfill.summary(count));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns a copy of the histogram corrected for coordinated omission, as
+    /// if each value had been recorded with
+    /// [`add_with_expected_interval`](Histogram::add_with_expected_interval).
+    /// This matches `copyCorrectedForCoordinatedOmission` in HdrHistogram.
+    ///
+    /// Each bucket is corrected as if its values were all at its upper bound,
+    /// so the result has no summary since the synthetic values are not exact.
+    ///
+    /// The counters are updated according to the [`CounterPolicy`]. Returns
+    /// `Err(Error::Overflow)` if the policy is [`CounterPolicy::Checked`] and
+    /// a counter would overflow.
+    ///
+    /// [`CounterPolicy`]: crate::CounterPolicy
+    /// [`CounterPolicy::Checked`]: crate::CounterPolicy::Checked
+    pub fn corrected_for_coordinated_omission(
+        &self,
+        expected_interval: u64,
+    ) -> Result<Histogram, Error> {
+        let policy = self.counter_policy;
+        let mut result = self.clone();
+        result.summary = None;
+
+        for (index, count) in self.buckets.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+
+            let value = self.config.index_to_upper_bound(index);
+            // the upper bounds are within the range, and so are the
+            // synthetic values
+            for backfill in backfill(&self.config, value, expected_interval) {
+                let bucket = &mut result.buckets[backfill.index.unwrap()];
+                *bucket = policy
+                    .mul(backfill.count, *count)
+                    .and_then(|n| policy.add(*bucket, n))?;
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+impl SparseHistogram {
+    /// Returns a copy of the histogram corrected for coordinated omission,
+    /// updating the counters according to its counter policy. See
+    /// [`Histogram::corrected_for_coordinated_omission`].
+    pub fn corrected_for_coordinated_omission(
+        &self,
+        expected_interval: u64,
+    ) -> Result<SparseHistogram, Error> {
+        let policy = self.counter_policy;
+        let mut buckets: BTreeMap<usize, u64> = self
+            .index
+            .iter()
+            .zip(&self.count)
+            .map(|(index, count)| (*index as usize, *count))
+            .collect();
+
+        for (index, count) in self.index.iter().zip(&self.count) {
+            let value = self.config.index_to_upper_bound(*index as usize);
+            for backfill in backfill(&self.config, value, expected_interval) {
+                let bucket = buckets.entry(backfill.index.unwrap()).or_insert(0);
+                *bucket = policy
+                    .mul(backfill.count, *count)
+                    .and_then(|n| policy.add(*bucket, n))?;
+            }
+        }
+
+        Ok(SparseHistogram {
+            config: self.config,
+            index: buckets.keys().map(|index| *index as u32).collect(),
+            count: buckets.into_values().collect(),
+            summary: None,
+            range_policy: self.range_policy,
+            overflow_count: self.overflow_count,
+            counter_policy: self.counter_policy,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CounterPolicy, RangePolicy};
+
+    /// Records the synthetic values one at a time.
+    fn naive(histogram: &mut Histogram, value: u64, count: u64, interval: u64) {
+        histogram.add(value, count).unwrap();
+        if interval == 0 {
+            return;
+        }
+        let mut missing = value.saturating_sub(interval);
+        while missing >= interval {
+            histogram.add(missing, count).unwrap();
+            missing -= interval;
+        }
+    }
+
+    #[test]
+    fn record() {
+        for (value, count, interval) in [
+            (1000, 1, 100),
+            (1000, 3, 7),
+            (1000, 1, 1),
+            (12345, 2, 1000),
+            (99, 1, 100),
+            (100, 1, 100),
+            (1000, 1, 0),
+        ] {
+            let mut expected = Histogram::new(4, 16).unwrap().with_summary();
+            naive(&mut expected, value, count, interval);
+
+            let mut histogram = Histogram::new(4, 16).unwrap().with_summary();
+            histogram
+                .add_with_expected_interval(value, count, interval)
+                .unwrap();
+
+            assert_eq!(histogram, expected, "{value} {count} {interval}");
+        }
+
+        let mut histogram = Histogram::new(4, 16).unwrap();
+        histogram
+            .increment_with_expected_interval(200, 100)
+            .unwrap();
+        assert_eq!(histogram.summary(), None);
+        let index = histogram.config().value_to_index(100).unwrap();
+        assert_eq!(histogram.as_slice()[index], 1);
+        assert_eq!(
+            histogram.increment_with_expected_interval(1 << 16, 100),
+            Err(Error::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn range_policy() {
+        let mut histogram = Histogram::new(4, 10)
+            .unwrap()
+            .with_summary()
+            .with_range_policy(RangePolicy::Count);
+        histogram
+            .increment_with_expected_interval(2500, 600)
+            .unwrap();
+        // 2500, 1900, and 1300 are above the max value of 1023
+        assert_eq!(histogram.overflow_count(), 3);
+        assert_eq!(histogram.as_slice().iter().sum::<u64>(), 1);
+        assert_eq!(histogram.summary().unwrap().sum(), 6400);
+
+        let mut histogram = Histogram::new(4, 10)
+            .unwrap()
+            .with_range_policy(RangePolicy::Clamp);
+        histogram
+            .increment_with_expected_interval(2500, 600)
+            .unwrap();
+        assert_eq!(histogram.as_slice().last(), Some(&3));
+        assert_eq!(histogram.as_slice().iter().sum::<u64>(), 4);
+    }
+
+    #[test]
+    fn large() {
+        // the work depends on the number of buckets, not values
+        let mut histogram = Histogram::new(7, 64).unwrap();
+        histogram
+            .increment_with_expected_interval(u64::MAX, 1)
+            .unwrap();
+        let total: u128 = histogram.as_slice().iter().map(|c| *c as u128).sum();
+        assert_eq!(total, u64::MAX as u128);
+    }
+
+    #[test]
+    fn corrected() {
+        let mut histogram = Histogram::new(4, 16).unwrap().with_summary();
+        histogram.add(10, 5).unwrap();
+        histogram.add(1000, 2).unwrap();
+
+        let mut expected = Histogram::new(4, 16).unwrap();
+        naive(&mut expected, 10, 5, 100);
+        // the upper bound of the bucket which contains 1000
+        naive(&mut expected, 1023, 2, 100);
+
+        let corrected = histogram.corrected_for_coordinated_omission(100).unwrap();
+        assert_eq!(corrected, expected);
+
+        let sparse = SparseHistogram::from(&histogram)
+            .corrected_for_coordinated_omission(100)
+            .unwrap();
+        assert_eq!(sparse, SparseHistogram::from(&expected));
+
+        // an interval of zero is a plain copy without the summary
+        let copy = histogram.corrected_for_coordinated_omission(0).unwrap();
+        assert_eq!(copy.as_slice(), histogram.as_slice());
+
+        // the counters follow the counter policy
+        let mut full = Histogram::new(4, 16)
+            .unwrap()
+            .with_counter_policy(CounterPolicy::Checked);
+        full.add(1000, u64::MAX).unwrap();
+        // in the same bucket as the synthetic value 923
+        full.increment(900).unwrap();
+        assert_eq!(
+            full.corrected_for_coordinated_omission(100),
+            Err(Error::Overflow)
+        );
+        assert_eq!(
+            SparseHistogram::from(&full).corrected_for_coordinated_omission(100),
+            Err(Error::Overflow)
+        );
+
+        let index = full.config().value_to_index(900).unwrap();
+        let full = full.with_counter_policy(CounterPolicy::Saturating);
+        let corrected = full.corrected_for_coordinated_omission(100).unwrap();
+        assert_eq!(corrected.as_slice()[index], u64::MAX);
+        assert_eq!(
+            SparseHistogram::from(&full)
+                .corrected_for_coordinated_omission(100)
+                .unwrap(),
+            SparseHistogram::from(&corrected)
+        );
+
+        let full = full.with_counter_policy(CounterPolicy::Wrapping);
+        let corrected = full.corrected_for_coordinated_omission(100).unwrap();
+        // 1 + u64::MAX
+        assert_eq!(corrected.as_slice()[index], 0);
+    }
+}
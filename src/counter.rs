@@ -0,0 +1,88 @@
+//! Handling of counters which overflow when a value is recorded.
+
+use crate::Error;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Selects what happens when recording a value overflows the counter of its
+/// bucket in a [`Histogram`](crate::Histogram) or
+/// [`AtomicHistogram`](crate::AtomicHistogram).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum CounterPolicy {
+    /// Wrap around to zero.
+    #[default]
+    Wrapping,
+    /// Stop at `u64::MAX`.
+    Saturating,
+    /// Return `Err(Error::Overflow)` and discard the value.
+    Checked,
+}
+
+impl CounterPolicy {
+    /// Returns the sum of the counts according to the policy.
+    pub(crate) fn add(self, a: u64, b: u64) -> Result<u64, Error> {
+        match self {
+            CounterPolicy::Wrapping => Ok(a.wrapping_add(b)),
+            CounterPolicy::Saturating => Ok(a.saturating_add(b)),
+            CounterPolicy::Checked => a.checked_add(b).ok_or(Error::Overflow),
+        }
+    }
+
+    /// Returns the product of the counts according to the policy.
+    pub(crate) fn mul(self, a: u64, b: u64) -> Result<u64, Error> {
+        match self {
+            CounterPolicy::Wrapping => Ok(a.wrapping_mul(b)),
+            CounterPolicy::Saturating => Ok(a.saturating_mul(b)),
+            CounterPolicy::Checked => a.checked_mul(b).ok_or(Error::Overflow),
+        }
+    }
+
+    /// Adds the count to an atomic counter according to the policy. The
+    /// counter is left unchanged if an error is returned.
+    pub(crate) fn fetch_add(self, counter: &AtomicU64, count: u64) -> Result<(), Error> {
+        match self {
+            CounterPolicy::Wrapping => {
+                counter.fetch_add(count, Ordering::Relaxed);
+                Ok(())
+            }
+            _ => counter
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| {
+                    self.add(c, count).ok()
+                })
+                .map(|_| ())
+                .map_err(|_| Error::Overflow),
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn is_default(&self) -> bool {
+        *self == CounterPolicy::Wrapping
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add() {
+        let max = u64::MAX;
+        assert_eq!(CounterPolicy::Wrapping.add(max, 2), Ok(1));
+        assert_eq!(CounterPolicy::Saturating.add(max, 2), Ok(max));
+        assert_eq!(CounterPolicy::Checked.add(max, 2), Err(Error::Overflow));
+        assert_eq!(CounterPolicy::Checked.add(1, 2), Ok(3));
+
+        let counter = AtomicU64::new(max - 1);
+        assert_eq!(
+            CounterPolicy::Checked.fetch_add(&counter, 2),
+            Err(Error::Overflow)
+        );
+        assert_eq!(counter.load(Ordering::Relaxed), max - 1);
+        CounterPolicy::Saturating.fetch_add(&counter, 2).unwrap();
+        assert_eq!(counter.load(Ordering::Relaxed), max);
+        CounterPolicy::Wrapping.fetch_add(&counter, 2).unwrap();
+        assert_eq!(counter.load(Ordering::Relaxed), 1);
+    }
+}
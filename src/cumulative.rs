@@ -0,0 +1,255 @@
+use crate::{Bucket, Config, Error, Histogram, Interpolation, Rank, SparseHistogram};
+
+/// A read-only snapshot of a histogram which stores the cumulative counts of
+/// the non-zero buckets.
+///
+/// Percentile and rank queries on a [`Histogram`] or [`SparseHistogram`]
+/// scan the buckets from the start on every call. This type pays that cost
+/// once when it is built and then answers each query with a binary search,
+/// which is useful when many queries are made against the same snapshot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CumulativeHistogram {
+    config: Config,
+    index: Vec<u32>,
+    cumulative: Vec<u128>,
+}
+
+impl CumulativeHistogram {
+    /// Returns the bucket configuration.
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Returns the total number of observations.
+    pub fn total_count(&self) -> u128 {
+        self.cumulative.last().copied().unwrap_or(0)
+    }
+
+    /// Returns the number of observations in the bucket at position `i`.
+    fn count(&self, i: usize) -> u128 {
+        match i {
+            0 => self.cumulative[0],
+            _ => self.cumulative[i] - self.cumulative[i - 1],
+        }
+    }
+
+    /// Returns the number of observations below the bucket at position `i`.
+    fn below(&self, i: usize) -> u128 {
+        match i {
+            0 => 0,
+            _ => self.cumulative[i - 1],
+        }
+    }
+
+    /// Returns the bucket at position `i`.
+    fn bucket(&self, i: usize) -> Bucket {
+        Bucket {
+            count: self.count(i) as u64,
+            range: self.config.index_to_range(self.index[i] as usize),
+        }
+    }
+
+    /// Returns the position of the bucket which contains the percentile.
+    fn position(&self, percentile: f64) -> usize {
+        // For 0.0 percentile (min) we need to report the first bucket with a
+        // non-zero count.
+        let count = std::cmp::max(1, (percentile * self.total_count() as f64).ceil() as u128);
+
+        // clamp to the last bucket to guard against rounding
+        self.cumulative
+            .partition_point(|c| *c < count)
+            .min(self.cumulative.len() - 1)
+    }
+
+    /// Return a collection of percentiles from this histogram.
+    ///
+    /// Each percentile should be in the inclusive range `0.0..=1.0`. For
+    /// example, the 50th percentile (median) can be found using `0.5`.
+    ///
+    /// The results will be sorted by the percentile.
+    pub fn percentiles(&self, percentiles: &[f64]) -> Result<Option<Vec<(f64, Bucket)>>, Error> {
+        self.percentiles_with(percentiles, |i, _| self.bucket(i))
+    }
+
+    /// Return a single percentile from this histogram.
+    ///
+    /// The percentile should be in the inclusive range `0.0..=1.0`. For
+    /// example, the 50th percentile (median) can be found using `0.5`.
+    pub fn percentile(&self, percentile: f64) -> Result<Option<Bucket>, Error> {
+        self.percentiles(&[percentile])
+            .map(|v| v.map(|x| x.first().unwrap().1.clone()))
+    }
+
+    /// Return a collection of percentiles from this histogram, each estimated
+    /// as a single value from its bucket according to the `interpolation`.
+    ///
+    /// The results will be sorted by the percentile.
+    pub fn percentile_values(
+        &self,
+        percentiles: &[f64],
+        interpolation: Interpolation,
+    ) -> Result<Option<Vec<(f64, f64)>>, Error> {
+        let total_count = self.total_count() as f64;
+
+        self.percentiles_with(percentiles, |i, percentile| {
+            interpolation.value(&self.bucket(i), self.below(i), percentile * total_count)
+        })
+    }
+
+    /// Return a single percentile from this histogram, estimated as a single
+    /// value from its bucket according to the `interpolation`.
+    pub fn percentile_value(
+        &self,
+        percentile: f64,
+        interpolation: Interpolation,
+    ) -> Result<Option<f64>, Error> {
+        self.percentile_values(&[percentile], interpolation)
+            .map(|v| v.map(|x| x.first().unwrap().1))
+    }
+
+    /// Validates and sorts the percentiles and maps each to a result using
+    /// the position of the bucket which contains it.
+    fn percentiles_with<T>(
+        &self,
+        percentiles: &[f64],
+        f: impl Fn(usize, f64) -> T,
+    ) -> Result<Option<Vec<(f64, T)>>, Error> {
+        // validate all the percentiles
+        for percentile in percentiles {
+            if !(0.0..=1.0).contains(percentile) {
+                return Err(Error::InvalidPercentile);
+            }
+        }
+
+        // empty histogram, no percentiles available
+        if self.cumulative.is_empty() {
+            return Ok(None);
+        }
+
+        let mut percentiles = percentiles.to_vec();
+        percentiles.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        Ok(Some(
+            percentiles
+                .iter()
+                .map(|percentile| (*percentile, f(self.position(*percentile), *percentile)))
+                .collect(),
+        ))
+    }
+
+    /// Returns the fraction of observations which are less than or equal to
+    /// the value, or `None` if the histogram is empty.
+    ///
+    /// Returns `Err(Error::OutOfRange)` if the value cannot be stored in the
+    /// histogram.
+    pub fn rank(&self, value: u64) -> Result<Option<Rank>, Error> {
+        let index = self.config.value_to_index(value)?;
+
+        // empty histogram, no ranks available
+        if self.cumulative.is_empty() {
+            return Ok(None);
+        }
+
+        let i = self.index.partition_point(|idx| (*idx as usize) < index);
+        let within = match self.index.get(i) {
+            Some(idx) if *idx as usize == index => self.count(i),
+            _ => 0,
+        };
+
+        Ok(Some(Rank::new(
+            value,
+            self.config.index_to_lower_bound(index),
+            self.config.index_to_upper_bound(index),
+            self.below(i),
+            within,
+            self.total_count(),
+        )))
+    }
+
+    /// Return the ranks of a collection of values from this histogram. See
+    /// [`rank`](Self::rank) for details.
+    ///
+    /// The results will be sorted by the value.
+    pub fn ranks(&self, values: &[u64]) -> Result<Option<Vec<(u64, Rank)>>, Error> {
+        let mut values = values.to_vec();
+        values.sort_unstable();
+
+        let mut result = Vec::with_capacity(values.len());
+        for value in values {
+            match self.rank(value)? {
+                Some(rank) => result.push((value, rank)),
+                None => return Ok(None),
+            }
+        }
+
+        Ok(Some(result))
+    }
+}
+
+impl From<&Histogram> for CumulativeHistogram {
+    fn from(histogram: &Histogram) -> Self {
+        Self::from(&SparseHistogram::from(histogram))
+    }
+}
+
+impl From<&SparseHistogram> for CumulativeHistogram {
+    fn from(histogram: &SparseHistogram) -> Self {
+        let mut total: u128 = 0;
+        let cumulative = histogram
+            .count
+            .iter()
+            .map(|count| {
+                total += *count as u128;
+                total
+            })
+            .collect();
+
+        Self {
+            config: histogram.config,
+            index: histogram.index.clone(),
+            cumulative,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{RngExt, SeedableRng};
+
+    #[test]
+    fn empty() {
+        let histogram = CumulativeHistogram::from(&Histogram::new(7, 64).unwrap());
+        assert_eq!(histogram.total_count(), 0);
+        assert_eq!(histogram.percentile(0.5), Ok(None));
+        assert_eq!(histogram.rank(10), Ok(None));
+        assert_eq!(histogram.ranks(&[10, 20]), Ok(None));
+        assert_eq!(histogram.percentile(2.0), Err(Error::InvalidPercentile));
+    }
+
+    #[test]
+    // Tests that results match the linear scans on the source histogram
+    fn matches_histogram() {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(42);
+        let mut histogram = Histogram::new(7, 32).unwrap();
+        for _ in 0..10_000 {
+            let _ = histogram.increment(rng.random_range(1..1_000_000));
+        }
+        let cumulative = CumulativeHistogram::from(&histogram);
+        assert_eq!(cumulative.total_count(), 10_000);
+
+        let percentiles = [0.0, 0.01, 0.25, 0.5, 0.75, 0.9, 0.99, 0.999, 1.0];
+        assert_eq!(
+            cumulative.percentiles(&percentiles),
+            histogram.percentiles(&percentiles)
+        );
+        assert_eq!(
+            cumulative.percentile_values(&percentiles, Interpolation::Linear),
+            histogram.percentile_values(&percentiles, Interpolation::Linear)
+        );
+
+        let values = [0, 1, 500, 123_456, 999_999, 4_000_000_000];
+        assert_eq!(cumulative.ranks(&values), histogram.ranks(&values));
+        assert_eq!(cumulative.rank(1 << 32), Err(Error::OutOfRange));
+    }
+}
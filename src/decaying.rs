@@ -0,0 +1,299 @@
+use crate::{Bucket, Config, Error};
+use std::time::{Duration, Instant};
+
+/// The number of half-lives after the landmark at which the weights are
+/// rescaled to keep them well within the range of an `f64`.
+const RESCALE_THRESHOLD: f64 = 256.0;
+
+/// A histogram where the weight of each observation decays exponentially
+/// with its age, so percentiles are biased towards recent observations.
+///
+/// This uses forward decay: rather than periodically decaying every counter,
+/// each observation is recorded with a weight that grows exponentially with
+/// the time elapsed since a fixed landmark. Since every observation decays at
+/// the same rate, the relative weights and therefore the percentiles do not
+/// depend on when the histogram is queried. The counters are rescaled
+/// occasionally to move the landmark forward and prevent overflow.
+///
+/// Time is supplied by the caller, which makes the histogram deterministic
+/// and usable with simulated clocks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecayingHistogram {
+    config: Config,
+    half_life: Duration,
+    landmark: Instant,
+    latest: Instant,
+    buckets: Box<[f64]>,
+}
+
+impl DecayingHistogram {
+    /// Construct a new decaying histogram from the provided parameters, with
+    /// observations losing half of their weight every `half_life`. See the
+    /// documentation for [`crate::Config`] to understand their meaning.
+    ///
+    /// Returns `Err(Error::IncompatibleParameters)` if the half-life is zero.
+    pub fn new(
+        grouping_power: u8,
+        max_value_power: u8,
+        half_life: Duration,
+        now: Instant,
+    ) -> Result<Self, Error> {
+        let config = Config::new(grouping_power, max_value_power)?;
+
+        Self::with_config(&config, half_life, now)
+    }
+
+    /// Creates a new decaying histogram using a provided [`crate::Config`],
+    /// with observations losing half of their weight every `half_life`.
+    ///
+    /// Returns `Err(Error::IncompatibleParameters)` if the half-life is zero.
+    pub fn with_config(config: &Config, half_life: Duration, now: Instant) -> Result<Self, Error> {
+        if half_life.is_zero() {
+            return Err(Error::IncompatibleParameters);
+        }
+
+        Ok(Self {
+            config: *config,
+            half_life,
+            landmark: now,
+            latest: now,
+            buckets: vec![0.0; config.total_buckets()].into(),
+        })
+    }
+
+    /// Returns the bucket configuration of the histogram.
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Returns the half-life of the observations.
+    pub fn half_life(&self) -> Duration {
+        self.half_life
+    }
+
+    /// Returns the number of half-lives between the landmark and `time`,
+    /// which is negative if `time` is before the landmark.
+    fn half_lives(&self, time: Instant) -> f64 {
+        let half_life = self.half_life.as_secs_f64();
+
+        if time >= self.landmark {
+            time.duration_since(self.landmark).as_secs_f64() / half_life
+        } else {
+            -self.landmark.duration_since(time).as_secs_f64() / half_life
+        }
+    }
+
+    /// Moves the landmark to `landmark`, scaling the counters so that the
+    /// decayed counts are unchanged.
+    fn rescale(&mut self, landmark: Instant) {
+        let factor = (-self.half_lives(landmark)).exp2();
+        for bucket in self.buckets.iter_mut() {
+            *bucket *= factor;
+        }
+        self.landmark = landmark;
+    }
+
+    /// Record a single observation of the value at time `now`.
+    pub fn increment(&mut self, value: u64, now: Instant) -> Result<(), Error> {
+        self.add(value, 1, now)
+    }
+
+    /// Record `count` observations of the value at time `now`.
+    pub fn add(&mut self, value: u64, count: u64, now: Instant) -> Result<(), Error> {
+        let index = self.config.value_to_index(value)?;
+
+        if self.half_lives(now) > RESCALE_THRESHOLD {
+            self.rescale(now);
+        }
+
+        self.buckets[index] += count as f64 * self.half_lives(now).exp2();
+        self.latest = self.latest.max(now);
+        Ok(())
+    }
+
+    /// Returns the total decayed count of the observations as of `now`.
+    pub fn count(&self, now: Instant) -> f64 {
+        let total: f64 = self.buckets.iter().sum();
+        total * (-self.half_lives(now)).exp2()
+    }
+
+    /// Merges the observations from the other histogram into this histogram.
+    ///
+    /// Returns `Err(Error::IncompatibleParameters)` if the histograms have
+    /// different configs or half-lives.
+    pub fn merge(&mut self, other: &DecayingHistogram) -> Result<(), Error> {
+        if self.config != other.config || self.half_life != other.half_life {
+            return Err(Error::IncompatibleParameters);
+        }
+
+        // use the later landmark so that the scaling factor for the other
+        // histogram cannot overflow
+        if other.landmark > self.landmark {
+            self.rescale(other.landmark);
+        }
+
+        let factor = self.half_lives(other.landmark).exp2();
+        for (this, other) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *this += other * factor;
+        }
+        self.latest = self.latest.max(other.latest);
+
+        Ok(())
+    }
+
+    /// Return a collection of percentiles from this histogram, weighting each
+    /// observation by its decayed count.
+    ///
+    /// Each percentile should be in the inclusive range `0.0..=1.0`. For
+    /// example, the 50th percentile (median) can be found using `0.5`.
+    ///
+    /// The results will be sorted by the percentile. The count of each
+    /// returned bucket is its decayed count as of the most recent
+    /// observation, rounded to the nearest integer.
+    pub fn percentiles(&self, percentiles: &[f64]) -> Result<Option<Vec<(f64, Bucket)>>, Error> {
+        // validate all the percentiles
+        for percentile in percentiles {
+            if !(0.0..=1.0).contains(percentile) {
+                return Err(Error::InvalidPercentile);
+            }
+        }
+
+        // get the total weight
+        let total: f64 = self.buckets.iter().sum();
+
+        // empty histogram, no percentiles available
+        if total <= 0.0 {
+            return Ok(None);
+        }
+
+        // sort the requested percentiles so we can find them in a single pass
+        let mut percentiles = percentiles.to_vec();
+        percentiles.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let decay = (-self.half_lives(self.latest)).exp2();
+        let last = self.buckets.iter().rposition(|w| *w > 0.0).unwrap();
+        let mut buckets = self
+            .buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, w)| **w > 0.0)
+            .peekable();
+        let mut partial_sum = 0.0;
+
+        let result = percentiles
+            .iter()
+            .map(|percentile| {
+                let target = percentile * total;
+
+                // advance until the cumulative weight reaches the target,
+                // falling back to the last bucket to guard against rounding
+                let index = loop {
+                    let (index, weight) = *buckets.peek().unwrap();
+                    if partial_sum + weight >= target || index == last {
+                        break index;
+                    }
+                    partial_sum += weight;
+                    buckets.next();
+                };
+
+                (
+                    *percentile,
+                    Bucket {
+                        count: (self.buckets[index] * decay).round() as u64,
+                        range: self.config.index_to_range(index),
+                    },
+                )
+            })
+            .collect();
+
+        Ok(Some(result))
+    }
+
+    /// Return a single percentile from this histogram. See
+    /// [`percentiles`](DecayingHistogram::percentiles) for details.
+    pub fn percentile(&self, percentile: f64) -> Result<Option<Bucket>, Error> {
+        self.percentiles(&[percentile])
+            .map(|v| v.map(|x| x.first().unwrap().1.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid() {
+        let now = Instant::now();
+        assert_eq!(
+            DecayingHistogram::new(7, 64, Duration::ZERO, now),
+            Err(Error::IncompatibleParameters)
+        );
+    }
+
+    #[test]
+    // Tests that recent observations have more weight
+    fn decay() {
+        let start = Instant::now();
+        let half_life = Duration::from_secs(10);
+        let mut histogram = DecayingHistogram::new(7, 64, half_life, start).unwrap();
+        assert_eq!(histogram.percentile(0.5), Ok(None));
+
+        histogram.add(10, 2, start).unwrap();
+        histogram.increment(1000, start + half_life).unwrap();
+        histogram.increment(1000, start + half_life).unwrap();
+
+        // the older observations now have a combined weight of 1.0
+        let count = histogram.count(start + half_life);
+        assert!((count - 3.0).abs() < 1e-9);
+        let count = histogram.count(start + 2 * half_life);
+        assert!((count - 1.5).abs() < 1e-9);
+
+        assert_eq!(histogram.percentile(0.0).unwrap().unwrap().end(), 10);
+        assert_eq!(histogram.percentile(0.3).unwrap().unwrap().end(), 10);
+        assert_eq!(histogram.percentile(0.5).unwrap().unwrap().end(), 1003);
+        let p100 = histogram.percentile(1.0).unwrap().unwrap();
+        assert_eq!(p100.end(), 1003);
+        assert_eq!(p100.count(), 2);
+
+        assert_eq!(histogram.percentile(1.5), Err(Error::InvalidPercentile));
+    }
+
+    #[test]
+    // Tests that rescaling keeps the weights finite
+    fn rescale() {
+        let start = Instant::now();
+        let half_life = Duration::from_millis(1);
+        let mut histogram = DecayingHistogram::new(7, 64, half_life, start).unwrap();
+
+        histogram.increment(1, start).unwrap();
+        for i in 1..=10 {
+            histogram
+                .increment(100, start + Duration::from_secs(i))
+                .unwrap();
+        }
+
+        let now = start + Duration::from_secs(10);
+        assert!(histogram.count(now).is_finite());
+        assert!((histogram.count(now) - 1.0).abs() < 1e-9);
+        assert_eq!(histogram.percentile(0.0).unwrap().unwrap().end(), 100);
+    }
+
+    #[test]
+    fn merge() {
+        let start = Instant::now();
+        let half_life = Duration::from_secs(1);
+        let mut a = DecayingHistogram::new(7, 64, half_life, start).unwrap();
+        let mut b = DecayingHistogram::new(7, 64, half_life, start + half_life).unwrap();
+
+        a.increment(10, start).unwrap();
+        b.increment(20, start + half_life).unwrap();
+        a.merge(&b).unwrap();
+
+        let count = a.count(start + half_life);
+        assert!((count - 1.5).abs() < 1e-9);
+        assert_eq!(a.percentile(0.4).unwrap().unwrap().end(), 20);
+
+        let c = DecayingHistogram::new(7, 64, half_life * 2, start).unwrap();
+        assert_eq!(a.merge(&c), Err(Error::IncompatibleParameters));
+    }
+}
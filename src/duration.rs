@@ -0,0 +1,401 @@
+use crate::bucket::Percentiles;
+use crate::{AtomicHistogram, Bucket, Config, Error, Histogram, Interpolation};
+use core::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The resolution at which a [`DurationHistogram`] records durations. Each
+/// duration is truncated to a whole number of units before it is recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum TimeUnit {
+    /// Record whole nanoseconds.
+    Nanoseconds,
+    /// Record whole microseconds.
+    Microseconds,
+    /// Record whole milliseconds.
+    Milliseconds,
+    /// Record whole seconds.
+    Seconds,
+}
+
+impl TimeUnit {
+    /// Returns the number of nanoseconds in one unit.
+    fn nanos(&self) -> u64 {
+        match self {
+            Self::Nanoseconds => 1,
+            Self::Microseconds => 1_000,
+            Self::Milliseconds => 1_000_000,
+            Self::Seconds => 1_000_000_000,
+        }
+    }
+
+    /// Converts a duration to a whole number of units, rounding down. Returns
+    /// an error if the number of units does not fit in a `u64`.
+    pub(crate) fn value(&self, duration: Duration) -> Result<u64, Error> {
+        u64::try_from(duration.as_nanos() / self.nanos() as u128).map_err(|_| Error::OutOfRange)
+    }
+
+    /// Converts a number of units to a duration.
+    pub(crate) fn duration(&self, value: u64) -> Duration {
+        match self {
+            Self::Nanoseconds => Duration::from_nanos(value),
+            Self::Microseconds => Duration::from_micros(value),
+            Self::Milliseconds => Duration::from_millis(value),
+            Self::Seconds => Duration::from_secs(value),
+        }
+    }
+
+    /// Converts a bucket of units to a bucket of durations.
+    fn bucket(&self, bucket: Bucket) -> Bucket<Duration> {
+        Bucket {
+            count: bucket.count(),
+            range: self.duration(bucket.start())..=self.duration(bucket.end()),
+        }
+    }
+}
+
+/// A histogram of [`Duration`] values, recorded as a whole number of
+/// [`TimeUnit`]s in a [`Histogram`].
+///
+/// The unit is kept with the histogram, including in its serialized form, so
+/// that the recorded values are always interpreted at the same resolution.
+/// The max value power of the config limits the longest duration in units, so
+/// for example a max value power of `40` allows durations of up to about 18
+/// minutes at nanosecond resolution.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct DurationHistogram {
+    unit: TimeUnit,
+    histogram: Histogram,
+}
+
+impl DurationHistogram {
+    /// Construct a new histogram from the provided parameters. See the
+    /// documentation for [`crate::Config`] to understand their meaning.
+    pub fn new(unit: TimeUnit, grouping_power: u8, max_value_power: u8) -> Result<Self, Error> {
+        Ok(Self {
+            unit,
+            histogram: Histogram::new(grouping_power, max_value_power)?,
+        })
+    }
+
+    /// Creates a new histogram using a provided [`crate::Config`].
+    pub fn with_config(unit: TimeUnit, config: &Config) -> Self {
+        Self {
+            unit,
+            histogram: Histogram::with_config(config),
+        }
+    }
+
+    /// Creates a duration histogram from a histogram of values in the unit.
+    pub fn from_histogram(unit: TimeUnit, histogram: Histogram) -> Self {
+        Self { unit, histogram }
+    }
+
+    /// Increment the counter for the bucket corresponding to the provided
+    /// duration by one (uses wrapping arithmetic on overflow).
+    pub fn increment(&mut self, duration: Duration) -> Result<(), Error> {
+        self.add(duration, 1)
+    }
+
+    /// Add some count to the counter for the bucket corresponding to the
+    /// provided duration. The counter uses wrapping arithmetic on overflow.
+    ///
+    /// Returns `Err(Error::OutOfRange)` if the duration in units is above the
+    /// max value of the config.
+    pub fn add(&mut self, duration: Duration, count: u64) -> Result<(), Error> {
+        self.histogram.add(self.unit.value(duration)?, count)
+    }
+
+    /// Returns the unit of the recorded values.
+    pub fn unit(&self) -> TimeUnit {
+        self.unit
+    }
+
+    /// Returns the bucket configuration of the histogram.
+    pub fn config(&self) -> Config {
+        self.histogram.config()
+    }
+
+    /// Returns the histogram of the recorded values in the unit.
+    pub fn histogram(&self) -> &Histogram {
+        &self.histogram
+    }
+
+    /// Consumes the duration histogram, returning the histogram of the
+    /// recorded values in the unit.
+    pub fn into_histogram(self) -> Histogram {
+        self.histogram
+    }
+
+    /// Return a collection of percentiles from this histogram, with the bucket
+    /// ranges as durations.
+    ///
+    /// Each percentile should be in the inclusive range `0.0..=1.0`. For
+    /// example, the 50th percentile (median) can be found using `0.5`.
+    ///
+    /// The results will be sorted by the percentile.
+    pub fn percentiles(&self, percentiles: &[f64]) -> Result<Option<Percentiles<Duration>>, Error> {
+        self.histogram.percentiles(percentiles).map(|v| {
+            v.map(|v| {
+                v.into_iter()
+                    .map(|(p, bucket)| (p, self.unit.bucket(bucket)))
+                    .collect()
+            })
+        })
+    }
+
+    /// Return a single percentile from this histogram.
+    ///
+    /// The percentile should be in the inclusive range `0.0..=1.0`. For
+    /// example, the 50th percentile (median) can be found using `0.5`.
+    pub fn percentile(&self, percentile: f64) -> Result<Option<Bucket<Duration>>, Error> {
+        self.percentiles(&[percentile])
+            .map(|v| v.map(|x| x.first().unwrap().1.clone()))
+    }
+
+    /// Return a collection of percentiles from this histogram, each estimated
+    /// as a single duration from its bucket according to the `interpolation`.
+    ///
+    /// The results will be sorted by the percentile.
+    pub fn percentile_values(
+        &self,
+        percentiles: &[f64],
+        interpolation: Interpolation,
+    ) -> Result<Option<Vec<(f64, Duration)>>, Error> {
+        // an estimate above the largest duration saturates
+        let nanos = self.unit.nanos() as f64;
+        let duration =
+            |value: f64| Duration::try_from_secs_f64(value * nanos / 1e9).unwrap_or(Duration::MAX);
+
+        self.histogram
+            .percentile_values(percentiles, interpolation)
+            .map(|v| {
+                v.map(|v| {
+                    v.into_iter()
+                        .map(|(p, value)| (p, duration(value)))
+                        .collect()
+                })
+            })
+    }
+
+    /// Return a single percentile from this histogram, estimated as a single
+    /// duration from its bucket according to the `interpolation`.
+    pub fn percentile_value(
+        &self,
+        percentile: f64,
+        interpolation: Interpolation,
+    ) -> Result<Option<Duration>, Error> {
+        self.percentile_values(&[percentile], interpolation)
+            .map(|v| v.map(|x| x.first().unwrap().1))
+    }
+
+    /// Adds the other histogram to this histogram and returns the result as a
+    /// new histogram.
+    ///
+    /// An error is returned if the two histograms have different units or
+    /// incompatible parameters, or if there is an overflow.
+    pub fn checked_add(&self, other: &DurationHistogram) -> Result<DurationHistogram, Error> {
+        self.combine(other, Histogram::checked_add)
+    }
+
+    /// Adds the other histogram to this histogram and returns the result as a
+    /// new histogram.
+    ///
+    /// An error is returned if the two histograms have different units or
+    /// incompatible parameters.
+    pub fn wrapping_add(&self, other: &DurationHistogram) -> Result<DurationHistogram, Error> {
+        self.combine(other, Histogram::wrapping_add)
+    }
+
+    /// Subtracts the other histogram from this histogram and returns the result
+    /// as a new histogram.
+    ///
+    /// An error is returned if the two histograms have different units or
+    /// incompatible parameters, or if there is an overflow.
+    pub fn checked_sub(&self, other: &DurationHistogram) -> Result<DurationHistogram, Error> {
+        self.combine(other, Histogram::checked_sub)
+    }
+
+    /// Subtracts the other histogram from this histogram and returns the result
+    /// as a new histogram.
+    ///
+    /// An error is returned if the two histograms have different units or
+    /// incompatible parameters.
+    pub fn wrapping_sub(&self, other: &DurationHistogram) -> Result<DurationHistogram, Error> {
+        self.combine(other, Histogram::wrapping_sub)
+    }
+
+    fn combine(
+        &self,
+        other: &DurationHistogram,
+        f: impl Fn(&Histogram, &Histogram) -> Result<Histogram, Error>,
+    ) -> Result<DurationHistogram, Error> {
+        if self.unit != other.unit {
+            return Err(Error::IncompatibleParameters);
+        }
+
+        Ok(Self {
+            unit: self.unit,
+            histogram: f(&self.histogram, &other.histogram)?,
+        })
+    }
+
+    /// Returns an iterator across the histogram, with the bucket ranges as
+    /// durations.
+    pub fn iter(&self) -> impl Iterator<Item = Bucket<Duration>> + '_ {
+        self.histogram.iter().map(|bucket| self.unit.bucket(bucket))
+    }
+}
+
+/// A histogram of [`Duration`] values which allows concurrent recording,
+/// backed by an [`AtomicHistogram`]. See [`DurationHistogram`].
+pub struct AtomicDurationHistogram {
+    unit: TimeUnit,
+    histogram: AtomicHistogram,
+}
+
+impl AtomicDurationHistogram {
+    /// Construct a new histogram from the provided parameters. See the
+    /// documentation for [`crate::Config`] to understand their meaning.
+    pub fn new(unit: TimeUnit, grouping_power: u8, max_value_power: u8) -> Result<Self, Error> {
+        Ok(Self {
+            unit,
+            histogram: AtomicHistogram::new(grouping_power, max_value_power)?,
+        })
+    }
+
+    /// Creates a new histogram using a provided [`crate::Config`].
+    pub fn with_config(unit: TimeUnit, config: &Config) -> Self {
+        Self {
+            unit,
+            histogram: AtomicHistogram::with_config(config),
+        }
+    }
+
+    /// Increment the bucket that contains the duration by one.
+    pub fn increment(&self, duration: Duration) -> Result<(), Error> {
+        self.add(duration, 1)
+    }
+
+    /// Increment the bucket that contains the duration by some count.
+    ///
+    /// Returns `Err(Error::OutOfRange)` if the duration in units is above the
+    /// max value of the config.
+    pub fn add(&self, duration: Duration, count: u64) -> Result<(), Error> {
+        self.histogram.add(self.unit.value(duration)?, count)
+    }
+
+    /// Returns the unit of the recorded values.
+    pub fn unit(&self) -> TimeUnit {
+        self.unit
+    }
+
+    /// Returns the bucket configuration of the histogram.
+    pub fn config(&self) -> Config {
+        self.histogram.config()
+    }
+
+    /// Returns the histogram of the recorded values in the unit.
+    pub fn histogram(&self) -> &AtomicHistogram {
+        &self.histogram
+    }
+
+    /// Read the bucket values into a new [`DurationHistogram`].
+    pub fn load(&self) -> DurationHistogram {
+        DurationHistogram::from_histogram(self.unit, self.histogram.load())
+    }
+
+    // NOTE: once stabilized, `target_has_atomic_load_store` is more correct. https://github.com/rust-lang/rust/issues/94039
+    #[cfg(target_has_atomic = "64")]
+    /// Drains the bucket values into a new [`DurationHistogram`]. See
+    /// [`AtomicHistogram::drain`].
+    pub fn drain(&self) -> DurationHistogram {
+        DurationHistogram::from_histogram(self.unit, self.histogram.drain())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units() {
+        let duration = Duration::new(3, 456_789_012);
+        assert_eq!(TimeUnit::Nanoseconds.value(duration), Ok(3_456_789_012));
+        assert_eq!(TimeUnit::Microseconds.value(duration), Ok(3_456_789));
+        assert_eq!(TimeUnit::Milliseconds.value(duration), Ok(3_456));
+        assert_eq!(TimeUnit::Seconds.value(duration), Ok(3));
+        assert_eq!(
+            TimeUnit::Nanoseconds.value(Duration::MAX),
+            Err(Error::OutOfRange)
+        );
+
+        for unit in [
+            TimeUnit::Nanoseconds,
+            TimeUnit::Microseconds,
+            TimeUnit::Milliseconds,
+            TimeUnit::Seconds,
+        ] {
+            assert_eq!(unit.value(unit.duration(42)), Ok(42));
+        }
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut histogram = DurationHistogram::new(TimeUnit::Microseconds, 7, 32).unwrap();
+        for millis in 1..=100 {
+            histogram.increment(Duration::from_millis(millis)).unwrap();
+        }
+        // truncated to the resolution
+        histogram.increment(Duration::from_nanos(999)).unwrap();
+
+        let p0 = histogram.percentile(0.0).unwrap().unwrap();
+        assert_eq!(p0.range(), Duration::ZERO..=Duration::ZERO);
+
+        let p50 = histogram.percentile(0.5).unwrap().unwrap();
+        assert!(p50.range().contains(&Duration::from_millis(50)));
+        assert_eq!(p50.start(), Duration::from_micros(49_920));
+
+        let p100 = histogram
+            .percentile_value(1.0, Interpolation::Upper)
+            .unwrap()
+            .unwrap();
+        assert_eq!(p100, Duration::from_micros(100_351));
+
+        assert_eq!(
+            histogram.increment(Duration::from_secs(5000)),
+            Err(Error::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn merge() {
+        let mut a = DurationHistogram::new(TimeUnit::Milliseconds, 4, 20).unwrap();
+        let b = DurationHistogram::new(TimeUnit::Microseconds, 4, 20).unwrap();
+        a.increment(Duration::from_millis(5)).unwrap();
+
+        assert_eq!(a.checked_add(&b), Err(Error::IncompatibleParameters));
+        let sum = a.checked_add(&a).unwrap();
+        assert_eq!(sum.checked_sub(&a).unwrap(), a);
+        assert_eq!(sum.iter().map(|b| b.count()).sum::<u64>(), 2);
+    }
+
+    #[test]
+    fn atomic() {
+        let histogram = AtomicDurationHistogram::new(TimeUnit::Nanoseconds, 7, 64).unwrap();
+        histogram.increment(Duration::from_nanos(42)).unwrap();
+        histogram.add(Duration::from_secs(1), 2).unwrap();
+
+        let snapshot = histogram.load();
+        assert_eq!(snapshot.unit(), TimeUnit::Nanoseconds);
+        let p = snapshot.percentile(1.0).unwrap().unwrap();
+        assert!(p.range().contains(&Duration::from_secs(1)));
+        assert_eq!(p.count(), 2);
+    }
+}
@@ -20,4 +20,8 @@ pub enum Error {
     Underflow,
     #[error("the histogram is not a subset")]
     InvalidSubset,
+    #[error("the conversion would lose precision")]
+    PrecisionLoss,
+    #[error("the encoded histogram is invalid or unsupported")]
+    InvalidEncoding,
 }
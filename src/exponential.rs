@@ -0,0 +1,480 @@
+use crate::{Bucket, Config, Error, Histogram, SparseHistogram, Summary};
+
+/// The scale range allowed by OpenTelemetry.
+const MIN_SCALE: i32 = -10;
+const MAX_SCALE: i32 = 20;
+
+/// Returns the index of the exponential bucket containing the positive value.
+/// Buckets are exclusive of their lower bound and inclusive of their upper
+/// bound.
+pub(crate) fn value_to_index(value: f64, scale: i32) -> i32 {
+    // log2 is exact for powers of two, which are the upper bounds of buckets
+    (value.log2() * 2_f64.powi(scale)).ceil() as i32 - 1
+}
+
+/// Returns the exclusive lower bound of the exponential bucket.
+pub(crate) fn index_to_lower_bound(index: i32, scale: i32) -> f64 {
+    (index as f64 * 2_f64.powi(-scale)).exp2()
+}
+
+/// Returns the midpoint of the exponential bucket.
+pub(crate) fn index_to_midpoint(index: i32, scale: i32) -> f64 {
+    (index_to_lower_bound(index, scale) + index_to_lower_bound(index + 1, scale)) / 2.0
+}
+
+/// Returns the scale used to represent histograms with the config, which is
+/// the coarsest scale that is finer than the buckets of the config.
+pub(crate) fn scale(config: &Config) -> i32 {
+    (config.grouping_power() as i32 + 1).min(MAX_SCALE)
+}
+
+/// Converts exponential buckets, in ascending order, to a sparse histogram
+/// with the provided config by counting each bucket in the bucket containing
+/// its midpoint, rounded to the nearest integer.
+pub(crate) fn to_sparse(
+    config: &Config,
+    scale: i32,
+    zero_count: u64,
+    positive: impl Iterator<Item = (i32, u64)>,
+) -> Result<SparseHistogram, Error> {
+    let mut index: Vec<u32> = Vec::new();
+    let mut count: Vec<u64> = Vec::new();
+
+    let zero = (zero_count != 0).then_some((0.0, zero_count));
+    let positive = positive.map(|(i, n)| (index_to_midpoint(i, scale).round(), n));
+
+    for (value, n) in zero.into_iter().chain(positive) {
+        if value > u64::MAX as f64 {
+            return Err(Error::OutOfRange);
+        }
+        let i = config.value_to_index(value as u64)? as u32;
+
+        // midpoints are ascending, so buckets are merged with the last
+        if index.last() == Some(&i) {
+            let last = count.last_mut().unwrap();
+            *last = last.checked_add(n).ok_or(Error::Overflow)?;
+        } else {
+            index.push(i);
+            count.push(n);
+        }
+    }
+
+    SparseHistogram::from_parts(*config, index, count)
+}
+
+/// A contiguous range of exponential buckets.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct ExponentialBuckets {
+    offset: i32,
+    counts: Vec<u64>,
+}
+
+impl ExponentialBuckets {
+    /// Creates a range of buckets where `counts[i]` is the count of the
+    /// bucket with index `offset + i`.
+    pub fn new(offset: i32, counts: Vec<u64>) -> Self {
+        Self { offset, counts }
+    }
+
+    /// Returns the index of the first bucket.
+    pub fn offset(&self) -> i32 {
+        self.offset
+    }
+
+    /// Returns the bucket counts.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Returns an iterator over the index and count of each non-zero bucket.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (i32, u64)> + '_ {
+        (self.offset..)
+            .zip(self.counts.iter().copied())
+            .filter(|(_, count)| *count != 0)
+    }
+
+    /// Adds to the count of the bucket, which must not be below any bucket
+    /// that has already been added.
+    fn push(&mut self, index: i32, count: u64) {
+        if self.counts.is_empty() {
+            self.offset = index;
+        }
+
+        let i = (index - self.offset) as usize;
+        if i >= self.counts.len() {
+            self.counts.resize(i + 1, 0);
+        }
+        self.counts[i] = self.counts[i].wrapping_add(count);
+    }
+}
+
+/// A histogram in the form of an OpenTelemetry `ExponentialHistogram` data
+/// point.
+///
+/// Bucket `i` at scale `s` covers the values in `(base^i, base^(i + 1)]`,
+/// where `base = 2^(2^-s)`. Zero is counted separately.
+///
+/// When converting from a [`Histogram`] or [`SparseHistogram`], the scale is
+/// one more than the grouping power, up to the maximum scale of 20. Each
+/// bucket is re-bucketed by its midpoint, including the buckets of width one
+/// in the linear region. For grouping powers up to 19, this guarantees that:
+/// - the midpoint of each exponential bucket is within the relative error of
+///   the config, [`Config::error`], of every value counted in it
+/// - converting back with [`to_histogram`](ExponentialHistogram::to_histogram)
+///   using the same config is exact
+///
+/// Higher grouping powers are limited to scale 20, and adjacent buckets may
+/// be merged.
+///
+/// The overflow count of a histogram recorded with
+/// [`RangePolicy::Count`](crate::RangePolicy) has no bucket to be converted
+/// into and is dropped. A [`Summary`] still includes those values.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct ExponentialHistogram {
+    scale: i32,
+    zero_count: u64,
+    positive: ExponentialBuckets,
+    negative: ExponentialBuckets,
+    sum: Option<f64>,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl ExponentialHistogram {
+    /// Creates an exponential histogram from its buckets.
+    ///
+    /// Returns `Err(Error::IncompatibleParameters)` if the scale is outside
+    /// of the range `-10..=20` allowed by OpenTelemetry.
+    pub fn new(
+        scale: i32,
+        zero_count: u64,
+        positive: ExponentialBuckets,
+        negative: ExponentialBuckets,
+    ) -> Result<Self, Error> {
+        if !(MIN_SCALE..=MAX_SCALE).contains(&scale) {
+            return Err(Error::IncompatibleParameters);
+        }
+
+        Ok(Self {
+            scale,
+            zero_count,
+            positive,
+            negative,
+            sum: None,
+            min: None,
+            max: None,
+        })
+    }
+
+    /// Sets the sum of the recorded values.
+    pub fn with_sum(mut self, sum: f64) -> Self {
+        self.sum = Some(sum);
+        self
+    }
+
+    /// Sets the minimum recorded value.
+    pub fn with_min(mut self, min: f64) -> Self {
+        self.min = Some(min);
+        self
+    }
+
+    /// Sets the maximum recorded value.
+    pub fn with_max(mut self, max: f64) -> Self {
+        self.max = Some(max);
+        self
+    }
+
+    /// Creates an exponential histogram at the given scale from the non-zero
+    /// buckets of a histogram, in ascending order.
+    pub(crate) fn from_buckets(
+        buckets: impl Iterator<Item = Bucket>,
+        summary: Option<Summary>,
+        scale: i32,
+    ) -> Self {
+        let mut zero_count = 0;
+        let mut positive = ExponentialBuckets::default();
+
+        for bucket in buckets {
+            if bucket.end() == 0 {
+                zero_count += bucket.count();
+                continue;
+            }
+
+            let midpoint = bucket.start() as f64 + (bucket.end() - bucket.start()) as f64 / 2.0;
+            positive.push(value_to_index(midpoint, scale), bucket.count());
+        }
+
+        Self {
+            scale,
+            zero_count,
+            positive,
+            negative: ExponentialBuckets::default(),
+            sum: summary.map(|s| s.sum() as f64),
+            min: summary.and_then(|s| s.min()).map(|v| v as f64),
+            max: summary.and_then(|s| s.max()).map(|v| v as f64),
+        }
+    }
+
+    /// Returns the scale, which determines the resolution of the buckets.
+    pub fn scale(&self) -> i32 {
+        self.scale
+    }
+
+    /// Returns the number of zero values.
+    pub fn zero_count(&self) -> u64 {
+        self.zero_count
+    }
+
+    /// Returns the buckets for positive values.
+    pub fn positive(&self) -> &ExponentialBuckets {
+        &self.positive
+    }
+
+    /// Returns the buckets for negative values, which are always empty for
+    /// histograms converted from this crate.
+    pub fn negative(&self) -> &ExponentialBuckets {
+        &self.negative
+    }
+
+    /// Returns the total count of all buckets.
+    pub fn count(&self) -> u64 {
+        self.positive
+            .counts
+            .iter()
+            .chain(self.negative.counts.iter())
+            .fold(self.zero_count, |total, count| total.wrapping_add(*count))
+    }
+
+    /// Returns the sum of the recorded values, if known.
+    pub fn sum(&self) -> Option<f64> {
+        self.sum
+    }
+
+    /// Returns the minimum recorded value, if known.
+    pub fn min(&self) -> Option<f64> {
+        self.min
+    }
+
+    /// Returns the maximum recorded value, if known.
+    pub fn max(&self) -> Option<f64> {
+        self.max
+    }
+
+    /// Returns the summary if the sum, minimum, and maximum are known and are
+    /// integers small enough to have been represented exactly.
+    fn summary(&self) -> Option<Summary> {
+        let exact = |v: f64| (v >= 0.0 && v < 2_f64.powi(53) && v.fract() == 0.0).then_some(v);
+
+        let (sum, min, max) = (exact(self.sum?)?, exact(self.min?)?, exact(self.max?)?);
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+
+        Some(Summary {
+            min: min as u64,
+            max: max as u64,
+            sum: sum as u128,
+            count,
+        })
+    }
+
+    /// Converts to a sparse histogram with the provided config. Each
+    /// exponential bucket is counted in the bucket containing its midpoint,
+    /// rounded to the nearest integer. The sum, minimum, and maximum are kept
+    /// as the [`Summary`] if they are known and below `2^53`, which ensures
+    /// they are exact.
+    ///
+    /// Returns an error if:
+    /// - any negative bucket is non-zero, or a midpoint is above the max value
+    ///   of the config (`OutOfRange`)
+    /// - the count of a bucket overflows (`Overflow`)
+    pub fn to_sparse(&self, config: &Config) -> Result<SparseHistogram, Error> {
+        if self.negative.iter().next().is_some() {
+            return Err(Error::OutOfRange);
+        }
+
+        let mut histogram = to_sparse(config, self.scale, self.zero_count, self.positive.iter())?;
+        histogram.summary = self.summary();
+        Ok(histogram)
+    }
+
+    /// Converts to a histogram with the provided config. See
+    /// [`to_sparse`](ExponentialHistogram::to_sparse) for details.
+    pub fn to_histogram(&self, config: &Config) -> Result<Histogram, Error> {
+        self.to_sparse(config).map(|h| Histogram::from(&h))
+    }
+}
+
+impl From<&Histogram> for ExponentialHistogram {
+    fn from(histogram: &Histogram) -> Self {
+        Self::from_buckets(
+            histogram.iter().filter(|bucket| bucket.count() != 0),
+            histogram.summary(),
+            scale(&histogram.config),
+        )
+    }
+}
+
+impl From<&SparseHistogram> for ExponentialHistogram {
+    fn from(histogram: &SparseHistogram) -> Self {
+        Self::from_buckets(
+            histogram.iter(),
+            histogram.summary,
+            scale(&histogram.config),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{RngExt, SeedableRng};
+
+    #[test]
+    fn index() {
+        // upper bounds are inclusive
+        assert_eq!(value_to_index(1.0, 0), -1);
+        assert_eq!(value_to_index(1.5, 0), 0);
+        assert_eq!(value_to_index(2.0, 0), 0);
+        assert_eq!(value_to_index(4.0, 1), 3);
+        assert_eq!(value_to_index(4.0, -1), 0);
+        assert_eq!(value_to_index(5.0, -1), 1);
+
+        for scale in [-2, 0, 3, 20] {
+            for index in [-5, 0, 1, 17, 100] {
+                let midpoint = index_to_midpoint(index, scale);
+                assert_eq!(value_to_index(midpoint, scale), index);
+            }
+        }
+    }
+
+    #[test]
+    // Tests that values are within the relative error of the config and that
+    // converting back is exact
+    fn error() {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(42);
+
+        for grouping_power in [0, 1, 2, 4, 7, 10, 14, 19] {
+            let config = Config::new(grouping_power, 64).unwrap();
+            let error = config.error() / 100.0;
+
+            let values: Vec<u64> = (0..1000)
+                .map(|_| {
+                    let power = rng.random_range(0..64);
+                    rng.random_range(0..=u64::MAX >> power)
+                })
+                .collect();
+
+            for value in values.iter().copied() {
+                let index = config.value_to_index(value).unwrap() as u32;
+                let single = SparseHistogram::from_parts(config, vec![index], vec![1]).unwrap();
+                let exponential = ExponentialHistogram::from(&single);
+                assert_eq!(exponential.scale(), grouping_power as i32 + 1);
+                assert_eq!(exponential.to_sparse(&config).unwrap(), single);
+
+                if value == 0 {
+                    assert_eq!(exponential.zero_count(), 1);
+                } else {
+                    let (index, _) = exponential.positive().iter().next().unwrap();
+                    let midpoint = index_to_midpoint(index, exponential.scale());
+                    let relative = (midpoint - value as f64).abs() / value as f64;
+                    assert!(relative <= error, "{value} {midpoint} {relative} {error}");
+                }
+            }
+
+            // keep the dense histograms small
+            if grouping_power > 10 {
+                continue;
+            }
+
+            let mut histogram = Histogram::with_config(&config);
+            for value in values {
+                histogram.increment(value).unwrap();
+            }
+
+            let exponential = ExponentialHistogram::from(&histogram);
+            assert_eq!(exponential.count(), 1000);
+            assert_eq!(exponential.to_histogram(&config).unwrap(), histogram);
+
+            let sparse = SparseHistogram::from(&histogram);
+            assert_eq!(ExponentialHistogram::from(&sparse), exponential);
+            assert_eq!(exponential.to_sparse(&config).unwrap(), sparse);
+        }
+    }
+
+    #[test]
+    // Tests the linear region, where buckets have a width of one
+    fn linear() {
+        let mut histogram = Histogram::new(3, 10).unwrap();
+        for value in 0..16 {
+            histogram.increment(value).unwrap();
+        }
+
+        let exponential = ExponentialHistogram::from(&histogram);
+        assert_eq!(exponential.zero_count(), 1);
+        assert_eq!(exponential.positive().iter().count(), 15);
+        assert_eq!(exponential.sum(), None);
+        assert_eq!(
+            exponential.to_histogram(&histogram.config()).unwrap(),
+            histogram
+        );
+    }
+
+    #[test]
+    // Tests converting data points which did not come from a histogram
+    fn import() {
+        let config = Config::new(7, 64).unwrap();
+        let exponential = ExponentialHistogram::new(
+            0,
+            2,
+            ExponentialBuckets::new(3, vec![1, 0, 4]),
+            ExponentialBuckets::default(),
+        )
+        .unwrap()
+        .with_sum(100.0)
+        .with_min(0.0)
+        .with_max(40.0);
+        assert_eq!(exponential.count(), 7);
+
+        // the midpoints of (8, 16] and (32, 64] are 12 and 48
+        let histogram = exponential.to_sparse(&config).unwrap();
+        assert_eq!(histogram.index(), &[0, 12, 48]);
+        assert_eq!(histogram.count(), &[2, 1, 4]);
+        let summary = histogram.summary().unwrap();
+        assert_eq!(summary.max(), Some(40));
+        assert_eq!(summary.count(), 7);
+
+        // convert to a coarser config
+        let coarse = Config::new(1, 64).unwrap();
+        let histogram = exponential.to_sparse(&coarse).unwrap();
+        assert_eq!(histogram.count(), &[2, 1, 4]);
+
+        let negative = ExponentialHistogram::new(
+            0,
+            0,
+            ExponentialBuckets::default(),
+            ExponentialBuckets::new(0, vec![1]),
+        )
+        .unwrap();
+        assert_eq!(negative.to_sparse(&config), Err(Error::OutOfRange));
+
+        let large = ExponentialHistogram::new(
+            0,
+            0,
+            ExponentialBuckets::new(64, vec![1]),
+            ExponentialBuckets::default(),
+        )
+        .unwrap();
+        assert_eq!(large.to_sparse(&config), Err(Error::OutOfRange));
+
+        assert_eq!(
+            ExponentialHistogram::new(21, 0, Default::default(), Default::default()),
+            Err(Error::IncompatibleParameters)
+        );
+    }
+}
@@ -0,0 +1,461 @@
+use crate::bucket::{self, Percentiles};
+use crate::{Bucket, Error};
+use core::ops::RangeInclusive;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The number of explicit mantissa bits in an `f64`.
+const MANTISSA_BITS: u8 = 52;
+
+/// The exponent bias of an `f64`.
+const EXPONENT_BIAS: i32 = 1023;
+
+/// The configuration of a [`FloatHistogram`], which determines the bucketing
+/// of `f64` values.
+///
+/// Like [`Config`](crate::Config), each power of two is divided into
+/// `2^grouping_power` buckets of equal width, so the relative error is
+/// `2^(-1 * grouping_power)`. The buckets are found directly from the exponent
+/// and the top `grouping_power` bits of the mantissa of the value.
+/// * `grouping_power` - controls the number of buckets that are used to span
+///   consecutive powers of two, and must be at most `52`.
+/// * `min_exponent` - values below `2^min_exponent`, including zero, are
+///   stored in a single bucket. Must be at least `-1022`.
+/// * `max_exponent` - values must be below `2^(max_exponent + 1)`. Must be at
+///   most `1023` and at least `min_exponent`.
+///
+/// For example, a grouping power of `7` with exponents from `-20` to `0`
+/// records values from `0.0` to `2.0` with a relative error of 0.78% above
+/// `2^-20`, using 2689 buckets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct FloatConfig {
+    grouping_power: u8,
+    min_exponent: i16,
+    max_exponent: i16,
+}
+
+impl FloatConfig {
+    /// Create a new `FloatConfig` from the parameters. See the struct
+    /// documentation for their meaning and constraints.
+    ///
+    /// Returns `Err(Error::IncompatibleParameters)` if the parameters are out
+    /// of range.
+    pub fn new(grouping_power: u8, min_exponent: i16, max_exponent: i16) -> Result<Self, Error> {
+        if grouping_power > MANTISSA_BITS
+            || min_exponent < 1 - EXPONENT_BIAS as i16
+            || max_exponent > EXPONENT_BIAS as i16
+            || min_exponent > max_exponent
+        {
+            return Err(Error::IncompatibleParameters);
+        }
+
+        let config = Self {
+            grouping_power,
+            min_exponent,
+            max_exponent,
+        };
+
+        // the buckets must be addressable
+        if usize::try_from(config.exponents() << grouping_power).is_err() {
+            return Err(Error::IncompatibleParameters);
+        }
+
+        Ok(config)
+    }
+
+    /// Returns the grouping power that was used to create this configuration.
+    pub fn grouping_power(&self) -> u8 {
+        self.grouping_power
+    }
+
+    /// Returns the minimum exponent that was used to create this
+    /// configuration.
+    pub fn min_exponent(&self) -> i16 {
+        self.min_exponent
+    }
+
+    /// Returns the maximum exponent that was used to create this
+    /// configuration.
+    pub fn max_exponent(&self) -> i16 {
+        self.max_exponent
+    }
+
+    /// Returns the relative error (in percentage) of this configuration. This
+    /// applies to values of at least `2^min_exponent`.
+    pub fn error(&self) -> f64 {
+        100.0 / 2_u64.pow(self.grouping_power as u32) as f64
+    }
+
+    /// Returns the largest value which can be stored in the histogram.
+    pub fn max(&self) -> f64 {
+        self.index_to_upper_bound(self.total_buckets() - 1)
+    }
+
+    /// Return the total number of buckets needed for this config.
+    pub fn total_buckets(&self) -> usize {
+        1 + (self.exponents() << self.grouping_power) as usize
+    }
+
+    /// Returns the number of powers of two between the exponents.
+    fn exponents(&self) -> u64 {
+        (self.max_exponent - self.min_exponent) as u64 + 1
+    }
+
+    /// Returns the bits of the lower bound of the bucket at the index, which
+    /// may be one past the last bucket.
+    fn lower_bound_bits(&self, index: usize) -> u64 {
+        let offset = index as u64 - 1;
+        let exponent =
+            (self.min_exponent as i32 + EXPONENT_BIAS) as u64 + (offset >> self.grouping_power);
+        let mantissa = offset & ((1 << self.grouping_power) - 1);
+
+        (exponent << MANTISSA_BITS) | (mantissa << (MANTISSA_BITS - self.grouping_power))
+    }
+
+    /// Converts a value to a bucket index. Returns an error if the value is
+    /// negative, not finite, or above the max value for the config.
+    pub(crate) fn value_to_index(&self, value: f64) -> Result<usize, Error> {
+        if value.is_nan() || value.is_sign_negative() && value != 0.0 {
+            return Err(Error::OutOfRange);
+        }
+
+        // values below the smallest power of two, including negative zero
+        if value < f64::from_bits(self.lower_bound_bits(1)) {
+            return Ok(0);
+        }
+
+        let bits = value.to_bits();
+        let exponent = (bits >> MANTISSA_BITS) as i32 - EXPONENT_BIAS;
+        if exponent > self.max_exponent as i32 {
+            return Err(Error::OutOfRange);
+        }
+
+        let offset = ((exponent - self.min_exponent as i32) as u64) << self.grouping_power;
+        let mantissa =
+            (bits >> (MANTISSA_BITS - self.grouping_power)) & ((1 << self.grouping_power) - 1);
+
+        Ok(1 + (offset + mantissa) as usize)
+    }
+
+    /// Convert a bucket index to a lower bound.
+    pub(crate) fn index_to_lower_bound(&self, index: usize) -> f64 {
+        match index {
+            0 => 0.0,
+            _ => f64::from_bits(self.lower_bound_bits(index)),
+        }
+    }
+
+    /// Convert a bucket index to an upper inclusive bound, which is the largest
+    /// `f64` below the lower bound of the next bucket.
+    pub(crate) fn index_to_upper_bound(&self, index: usize) -> f64 {
+        f64::from_bits(self.lower_bound_bits(index + 1) - 1)
+    }
+
+    /// Convert a bucket index to a range.
+    pub(crate) fn index_to_range(&self, index: usize) -> RangeInclusive<f64> {
+        self.index_to_lower_bound(index)..=self.index_to_upper_bound(index)
+    }
+}
+
+/// A histogram of non-negative `f64` values with plain 64bit counters for each
+/// bucket. See [`FloatConfig`] for the bucketing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct FloatHistogram {
+    config: FloatConfig,
+    buckets: Box<[u64]>,
+}
+
+impl FloatHistogram {
+    /// Construct a new histogram from the provided parameters. See the
+    /// documentation for [`FloatConfig`] to understand their meaning.
+    pub fn new(grouping_power: u8, min_exponent: i16, max_exponent: i16) -> Result<Self, Error> {
+        let config = FloatConfig::new(grouping_power, min_exponent, max_exponent)?;
+
+        Ok(Self::with_config(&config))
+    }
+
+    /// Creates a new histogram using a provided [`FloatConfig`].
+    pub fn with_config(config: &FloatConfig) -> Self {
+        Self {
+            config: *config,
+            buckets: vec![0; config.total_buckets()].into(),
+        }
+    }
+
+    /// Increment the counter for the bucket corresponding to the provided value
+    /// by one (uses wrapping arithmetic on overflow).
+    pub fn increment(&mut self, value: f64) -> Result<(), Error> {
+        self.add(value, 1)
+    }
+
+    /// Add some count to the counter for the bucket corresponding to the
+    /// provided value. The counter uses wrapping arithmetic on overflow.
+    ///
+    /// Returns `Err(Error::OutOfRange)` if the value is negative, not finite,
+    /// or above the max value of the config.
+    pub fn add(&mut self, value: f64, count: u64) -> Result<(), Error> {
+        let index = self.config.value_to_index(value)?;
+        self.buckets[index] = self.buckets[index].wrapping_add(count);
+        Ok(())
+    }
+
+    /// Returns the bucket configuration of the histogram.
+    pub fn config(&self) -> FloatConfig {
+        self.config
+    }
+
+    /// Get a reference to the raw counters.
+    pub fn as_slice(&self) -> &[u64] {
+        &self.buckets
+    }
+
+    /// Returns the total number of observations.
+    pub fn total_count(&self) -> u128 {
+        self.buckets.iter().map(|c| *c as u128).sum()
+    }
+
+    /// Return a collection of percentiles from this histogram.
+    ///
+    /// Each percentile should be in the inclusive range `0.0..=1.0`. For
+    /// example, the 50th percentile (median) can be found using `0.5`.
+    ///
+    /// The results will be sorted by the percentile.
+    pub fn percentiles(&self, percentiles: &[f64]) -> Result<Option<Percentiles<f64>>, Error> {
+        bucket::percentiles(self.iter(), self.total_count(), percentiles)
+    }
+
+    /// Return a single percentile from this histogram.
+    ///
+    /// The percentile should be in the inclusive range `0.0..=1.0`. For
+    /// example, the 50th percentile (median) can be found using `0.5`.
+    pub fn percentile(&self, percentile: f64) -> Result<Option<Bucket<f64>>, Error> {
+        self.percentiles(&[percentile])
+            .map(|v| v.map(|x| x.first().unwrap().1.clone()))
+    }
+
+    /// Adds the other histogram to this histogram and returns the result as a
+    /// new histogram.
+    ///
+    /// An error is returned if the two histograms have incompatible parameters
+    /// or if there is an overflow.
+    pub fn checked_add(&self, other: &FloatHistogram) -> Result<FloatHistogram, Error> {
+        self.combine(other, |a, b| a.checked_add(b).ok_or(Error::Overflow))
+    }
+
+    /// Adds the other histogram to this histogram and returns the result as a
+    /// new histogram.
+    ///
+    /// An error is returned if the two histograms have incompatible parameters.
+    pub fn wrapping_add(&self, other: &FloatHistogram) -> Result<FloatHistogram, Error> {
+        self.combine(other, |a, b| Ok(a.wrapping_add(b)))
+    }
+
+    /// Subtracts the other histogram from this histogram and returns the result
+    /// as a new histogram.
+    ///
+    /// An error is returned if the two histograms have incompatible parameters
+    /// or if there is an overflow.
+    pub fn checked_sub(&self, other: &FloatHistogram) -> Result<FloatHistogram, Error> {
+        self.combine(other, |a, b| a.checked_sub(b).ok_or(Error::Underflow))
+    }
+
+    /// Subtracts the other histogram from this histogram and returns the result
+    /// as a new histogram.
+    ///
+    /// An error is returned if the two histograms have incompatible parameters.
+    pub fn wrapping_sub(&self, other: &FloatHistogram) -> Result<FloatHistogram, Error> {
+        self.combine(other, |a, b| Ok(a.wrapping_sub(b)))
+    }
+
+    /// Combines the counters of the two histograms bucket by bucket.
+    fn combine(
+        &self,
+        other: &FloatHistogram,
+        f: impl Fn(u64, u64) -> Result<u64, Error>,
+    ) -> Result<FloatHistogram, Error> {
+        if self.config != other.config {
+            return Err(Error::IncompatibleParameters);
+        }
+
+        let mut result = self.clone();
+        for (this, other) in result.buckets.iter_mut().zip(other.buckets.iter()) {
+            *this = f(*this, *other)?;
+        }
+
+        Ok(result)
+    }
+
+    /// Returns an iterator across the histogram.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            index: 0,
+            histogram: self,
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a FloatHistogram {
+    type Item = Bucket<f64>;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// An iterator across the histogram buckets.
+pub struct Iter<'a> {
+    index: usize,
+    histogram: &'a FloatHistogram,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Bucket<f64>;
+
+    fn next(&mut self) -> Option<<Self as std::iter::Iterator>::Item> {
+        let count = *self.histogram.buckets.get(self.index)?;
+        let bucket = Bucket {
+            count,
+            range: self.histogram.config.index_to_range(self.index),
+        };
+
+        self.index += 1;
+
+        Some(bucket)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.len();
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {
+    fn len(&self) -> usize {
+        self.histogram.buckets.len() - self.index
+    }
+}
+
+impl std::iter::FusedIterator for Iter<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config() {
+        let config = FloatConfig::new(7, -20, 0).unwrap();
+        assert_eq!(config.total_buckets(), 2689);
+        assert_eq!(config.error(), 0.78125);
+        assert_eq!(config.max(), 2.0 - f64::EPSILON);
+
+        assert!(FloatConfig::new(10, -1022, 1023).is_ok());
+        assert_eq!(
+            FloatConfig::new(53, 0, 1),
+            Err(Error::IncompatibleParameters)
+        );
+        assert_eq!(
+            FloatConfig::new(7, -1023, 0),
+            Err(Error::IncompatibleParameters)
+        );
+        assert_eq!(
+            FloatConfig::new(7, 1, 0),
+            Err(Error::IncompatibleParameters)
+        );
+    }
+
+    #[test]
+    fn buckets() {
+        let config = FloatConfig::new(7, -20, 10).unwrap();
+
+        // the buckets are contiguous
+        for index in 0..config.total_buckets() - 1 {
+            let next = config.index_to_lower_bound(index + 1);
+            assert_eq!(
+                config.index_to_upper_bound(index).to_bits() + 1,
+                next.to_bits()
+            );
+            assert_eq!(config.value_to_index(next), Ok(index + 1));
+        }
+
+        for value in [
+            0.001,
+            0.1,
+            0.5,
+            1.0,
+            1.0 / 3.0,
+            std::f64::consts::PI,
+            1000.0,
+            2047.9,
+        ] {
+            let index = config.value_to_index(value).unwrap();
+            let range = config.index_to_range(index);
+            assert!(range.contains(&value));
+            assert!((range.end() - range.start()) / range.start() <= config.error() / 100.0);
+        }
+
+        assert_eq!(config.value_to_index(0.0), Ok(0));
+        assert_eq!(config.value_to_index(-0.0), Ok(0));
+        assert_eq!(config.value_to_index(1e-7), Ok(0));
+        assert_eq!(config.value_to_index(2048.0), Err(Error::OutOfRange));
+        assert_eq!(config.value_to_index(-1.0), Err(Error::OutOfRange));
+        assert_eq!(config.value_to_index(f64::NAN), Err(Error::OutOfRange));
+        assert_eq!(config.value_to_index(f64::INFINITY), Err(Error::OutOfRange));
+
+        // the full range of finite values
+        let config = FloatConfig::new(0, -1022, 1023).unwrap();
+        assert_eq!(config.max(), f64::MAX);
+        assert_eq!(
+            config.value_to_index(f64::MAX),
+            Ok(config.total_buckets() - 1)
+        );
+        assert_eq!(config.value_to_index(f64::MIN_POSITIVE), Ok(1));
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut histogram = FloatHistogram::new(7, -20, 0).unwrap();
+        for i in 0..=100 {
+            histogram.increment(i as f64 / 100.0).unwrap();
+        }
+        assert_eq!(histogram.total_count(), 101);
+        assert_eq!(histogram.increment(2.0), Err(Error::OutOfRange));
+
+        let percentiles = histogram
+            .percentiles(&[0.0, 0.5, 0.99, 1.0])
+            .unwrap()
+            .unwrap();
+        let zero = histogram.config().index_to_range(0);
+        assert_eq!(percentiles[0].1.range(), zero);
+        assert!(percentiles[1].1.range().contains(&0.5));
+        assert!(percentiles[2].1.range().contains(&0.99));
+        assert!(percentiles[3].1.range().contains(&1.0));
+
+        assert_eq!(histogram.percentile(-0.1), Err(Error::InvalidPercentile));
+        let empty = FloatHistogram::new(7, -20, 0).unwrap();
+        assert_eq!(empty.percentile(0.5), Ok(None));
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut a = FloatHistogram::new(4, -10, 10).unwrap();
+        let mut b = FloatHistogram::new(4, -10, 10).unwrap();
+        a.increment(0.25).unwrap();
+        b.increment(0.25).unwrap();
+        b.increment(100.0).unwrap();
+
+        let sum = a.checked_add(&b).unwrap();
+        assert_eq!(sum.total_count(), 3);
+        assert_eq!(sum.checked_sub(&b).unwrap(), a);
+        assert_eq!(a.checked_sub(&b), Err(Error::Underflow));
+        assert_eq!(a.wrapping_add(&b).unwrap(), sum);
+
+        let c = FloatHistogram::new(4, -10, 11).unwrap();
+        assert_eq!(a.checked_add(&c), Err(Error::IncompatibleParameters));
+    }
+}
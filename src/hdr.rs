@@ -0,0 +1,593 @@
+//! Conversion to and from the HdrHistogram V2 encoding.
+//!
+//! With a unit magnitude of zero, an HdrHistogram with a sub-bucket count of
+//! `2^m` uses exactly the same bucket layout as a [`Config`] with a grouping
+//! power of `m - 1`, so buckets map one-to-one between the two.
+
+use crate::{Config, Error, Histogram, SparseHistogram};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::io::{Read, Write};
+
+/// Identifies an uncompressed V2 encoding.
+const V2_COOKIE: u32 = 0x1c84_9303;
+
+/// Identifies a V2 encoding compressed with DEFLATE.
+const V2_COMPRESSED_COOKIE: u32 = 0x1c84_9304;
+
+/// The reference implementations set this flag in the cookie to indicate the
+/// word size of the encoded counts, and mask it out when decoding.
+const COOKIE_FLAGS: u32 = 0xf0;
+
+/// Set in the cookies we encode, matching the reference implementations.
+const COOKIE_WORD_SIZE: u32 = 0x10;
+
+/// The maximum number of significant digits supported by HdrHistogram.
+const MAX_DIGITS: u32 = 5;
+
+/// The length of the header of an uncompressed encoding in bytes.
+const HEADER_LEN: usize = 40;
+
+/// The most bytes taken by a single varint.
+const MAX_VARINT_LEN: usize = 9;
+
+/// Returns the sub-bucket count magnitude used by HdrHistogram for the
+/// number of significant digits, which is `ceil(log2(2 * 10^digits))`.
+fn sub_bucket_magnitude(digits: u32) -> u8 {
+    let largest = 2 * 10_u64.pow(digits);
+    (64 - (largest - 1).leading_zeros()) as u8
+}
+
+/// Returns the config sharing the bucket layout of an HdrHistogram with the
+/// given sub-bucket count magnitude and a unit magnitude of zero.
+fn layout(magnitude: u8) -> Config {
+    // the magnitude is at most 18, so this cannot fail
+    Config::new(magnitude - 1, 64).unwrap()
+}
+
+/// Returns the length of the longest valid payload, which has a varint of the
+/// most bytes for every bucket of the finest layout.
+fn max_payload_len() -> usize {
+    layout(sub_bucket_magnitude(MAX_DIGITS)).total_buckets() * MAX_VARINT_LEN
+}
+
+/// Encodes a value using the ZigZag LEB128 variant from HdrHistogram, where
+/// the ninth byte, if needed, holds the remaining eight bits.
+fn write_varint(buf: &mut Vec<u8>, value: i64) {
+    let mut value = ((value << 1) ^ (value >> 63)) as u64;
+
+    for _ in 0..8 {
+        if value >> 7 == 0 {
+            buf.push(value as u8);
+            return;
+        }
+        buf.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+
+    buf.push(value as u8);
+}
+
+/// Decodes a value written by [`write_varint`].
+fn read_varint(buf: &mut &[u8]) -> Result<i64, Error> {
+    let mut value = 0;
+
+    for shift in (0..56).step_by(7) {
+        let byte = read_u8(buf)?;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(((value >> 1) as i64) ^ -((value & 1) as i64));
+        }
+    }
+    value |= (read_u8(buf)? as u64) << 56;
+
+    Ok(((value >> 1) as i64) ^ -((value & 1) as i64))
+}
+
+fn read_bytes<const N: usize>(buf: &mut &[u8]) -> Result<[u8; N], Error> {
+    let (bytes, rest) = buf.split_first_chunk().ok_or(Error::InvalidEncoding)?;
+    *buf = rest;
+    Ok(*bytes)
+}
+
+fn read_u8(buf: &mut &[u8]) -> Result<u8, Error> {
+    read_bytes::<1>(buf).map(|b| b[0])
+}
+
+fn read_u32(buf: &mut &[u8]) -> Result<u32, Error> {
+    read_bytes(buf).map(u32::from_be_bytes)
+}
+
+fn read_u64(buf: &mut &[u8]) -> Result<u64, Error> {
+    read_bytes(buf).map(u64::from_be_bytes)
+}
+
+/// Encodes the non-zero buckets, in ascending index order, into the
+/// uncompressed V2 encoding.
+fn encode(config: &Config, buckets: impl Iterator<Item = (usize, u64)>) -> Result<Vec<u8>, Error> {
+    // use the coarsest layout that is at least as fine as the config
+    let digits = (0..=MAX_DIGITS)
+        .find(|digits| sub_bucket_magnitude(*digits) > config.grouping_power())
+        .ok_or(Error::PrecisionLoss)?;
+    let layout = layout(sub_bucket_magnitude(digits));
+
+    let max = config.index_to_upper_bound(config.total_buckets() - 1);
+    let highest = max.min(i64::MAX as u64);
+
+    let mut payload = Vec::new();
+    let mut next = 0;
+    for (index, count) in buckets {
+        // each bucket is a union of buckets in the finer layout, and the last
+        // of those has the same upper bound, which HdrHistogram reports
+        let value = config.index_to_upper_bound(index);
+        if value > highest {
+            return Err(Error::OutOfRange);
+        }
+        let count = i64::try_from(count).map_err(|_| Error::Overflow)?;
+
+        // the layout covers the entire range of a u64, so this cannot fail
+        let index = layout.value_to_index(value).unwrap();
+        match index - next {
+            0 => {}
+            1 => write_varint(&mut payload, 0),
+            zeros => write_varint(&mut payload, -(zeros as i64)),
+        }
+        write_varint(&mut payload, count);
+        next = index + 1;
+    }
+
+    let payload_len = u32::try_from(payload.len()).map_err(|_| Error::Overflow)?;
+
+    let mut buf = Vec::with_capacity(HEADER_LEN + payload.len());
+    buf.extend_from_slice(&(V2_COOKIE | COOKIE_WORD_SIZE).to_be_bytes());
+    buf.extend_from_slice(&payload_len.to_be_bytes());
+    // normalizing index offset
+    buf.extend_from_slice(&0_u32.to_be_bytes());
+    buf.extend_from_slice(&digits.to_be_bytes());
+    // lowest discernible value
+    buf.extend_from_slice(&1_u64.to_be_bytes());
+    buf.extend_from_slice(&highest.to_be_bytes());
+    // integer to double value conversion ratio
+    buf.extend_from_slice(&1.0_f64.to_be_bytes());
+    buf.extend_from_slice(&payload);
+
+    Ok(buf)
+}
+
+/// Compresses an uncompressed V2 encoding.
+fn compress(encoded: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    // writing into a vec cannot fail
+    encoder.write_all(encoded).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let len = u32::try_from(compressed.len()).map_err(|_| Error::Overflow)?;
+
+    let mut buf = Vec::with_capacity(8 + compressed.len());
+    buf.extend_from_slice(&(V2_COMPRESSED_COOKIE | COOKIE_WORD_SIZE).to_be_bytes());
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(&compressed);
+
+    Ok(buf)
+}
+
+/// Decodes a compressed or uncompressed V2 encoding.
+fn decode(bytes: &[u8]) -> Result<SparseHistogram, Error> {
+    let mut buf = bytes;
+
+    match read_u32(&mut buf)? & !COOKIE_FLAGS {
+        V2_COOKIE => decode_uncompressed(bytes),
+        V2_COMPRESSED_COOKIE => {
+            let len = read_u32(&mut buf)? as usize;
+            let compressed = buf.get(..len).ok_or(Error::InvalidEncoding)?;
+
+            // only decompress as much as the header says is there, which is
+            // bounded by the longest payload, so the input cannot expand
+            // without limit
+            let mut decoder = ZlibDecoder::new(compressed);
+            let mut decoded = vec![0; HEADER_LEN];
+            decoder
+                .read_exact(&mut decoded)
+                .map_err(|_| Error::InvalidEncoding)?;
+
+            let payload_len = read_u32(&mut &decoded[4..])? as usize;
+            if payload_len > max_payload_len() {
+                return Err(Error::InvalidEncoding);
+            }
+            decoder
+                .take(payload_len as u64)
+                .read_to_end(&mut decoded)
+                .map_err(|_| Error::InvalidEncoding)?;
+
+            decode_uncompressed(&decoded)
+        }
+        _ => Err(Error::InvalidEncoding),
+    }
+}
+
+fn decode_uncompressed(bytes: &[u8]) -> Result<SparseHistogram, Error> {
+    let mut buf = bytes;
+
+    if read_u32(&mut buf)? & !COOKIE_FLAGS != V2_COOKIE {
+        return Err(Error::InvalidEncoding);
+    }
+
+    let payload_len = read_u32(&mut buf)? as usize;
+    let normalizing_offset = read_u32(&mut buf)?;
+    let digits = read_u32(&mut buf)?;
+    let lowest = read_u64(&mut buf)?;
+    let highest = read_u64(&mut buf)?;
+    let _ratio = read_u64(&mut buf)?;
+
+    // shifted histograms are not supported
+    if normalizing_offset != 0 || digits > MAX_DIGITS || lowest == 0 || lowest > highest / 2 {
+        return Err(Error::InvalidEncoding);
+    }
+
+    let mut payload = buf.get(..payload_len).ok_or(Error::InvalidEncoding)?;
+
+    let magnitude = sub_bucket_magnitude(digits);
+    let unit = 63 - lowest.leading_zeros();
+    let layout = layout(magnitude);
+
+    // the inclusive value range and count of each non-zero bucket
+    let mut buckets = Vec::new();
+    let mut index: u64 = 0;
+    while !payload.is_empty() {
+        let count = read_varint(&mut payload)?;
+
+        if count < 0 {
+            index = index
+                .checked_add(count.unsigned_abs())
+                .ok_or(Error::InvalidEncoding)?;
+            continue;
+        }
+
+        if count > 0 {
+            if index >= layout.total_buckets() as u64 {
+                return Err(Error::InvalidEncoding);
+            }
+
+            let lower = layout.index_to_lower_bound(index as usize);
+            let upper = layout.index_to_upper_bound(index as usize);
+
+            // scale by the unit magnitude
+            if unit > 0 && upper >> (64 - unit) != 0 {
+                return Err(Error::InvalidEncoding);
+            }
+            let lower = lower << unit;
+            let upper = ((upper + 1) << unit).wrapping_sub(1);
+
+            buckets.push((lower, upper, count as u64));
+        }
+
+        index += 1;
+    }
+
+    let max = buckets.last().map(|b| b.1).unwrap_or(0).max(highest);
+    let max_value_power = (64 - max.leading_zeros() as u8).max(magnitude);
+    let config = Config::new(magnitude - 1, max_value_power)?;
+
+    let mut index = Vec::with_capacity(buckets.len());
+    let mut count = Vec::with_capacity(buckets.len());
+    for (lower, upper, n) in buckets {
+        let i = config.value_to_index(lower)?;
+
+        // only a unit magnitude above zero can make a bucket span several
+        // buckets of the config
+        if config.index_to_upper_bound(i) != upper {
+            return Err(Error::PrecisionLoss);
+        }
+
+        index.push(i as u32);
+        count.push(n);
+    }
+
+    SparseHistogram::from_parts(config, index, count)
+}
+
+impl Histogram {
+    /// Encodes the histogram in the uncompressed HdrHistogram V2 format.
+    ///
+    /// The encoding uses the HdrHistogram layout with the fewest significant
+    /// digits that is at least as precise as this histogram, see the table in
+    /// the [crate documentation](crate#hdrhistogram). Each count is stored in
+    /// the HdrHistogram bucket which shares the upper bound of its bucket
+    /// here, so the values reported by HdrHistogram match
+    /// [`Bucket::end`](crate::Bucket::end) and are within the relative error
+    /// of the config. The [`Summary`](crate::Summary), if any, is not
+    /// encoded.
+    ///
+    /// Returns an error if:
+    /// - the grouping power is above 17, which needs more than the five
+    ///   significant digits supported by HdrHistogram (`PrecisionLoss`)
+    /// - a non-zero bucket extends above `i64::MAX` (`OutOfRange`)
+    /// - the overflow count of [`RangePolicy::Count`](crate::RangePolicy) is
+    ///   non-zero, since those values have no bucket to be encoded in
+    ///   (`OutOfRange`)
+    /// - a count is above `i64::MAX` (`Overflow`)
+    pub fn to_hdr(&self) -> Result<Vec<u8>, Error> {
+        if self.overflow_count != 0 {
+            return Err(Error::OutOfRange);
+        }
+
+        let buckets = self
+            .buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count != 0)
+            .map(|(index, count)| (index, *count));
+
+        encode(&self.config, buckets)
+    }
+
+    /// Encodes the histogram in the HdrHistogram V2 format, compressed with
+    /// DEFLATE. See [`to_hdr`](Histogram::to_hdr) for details.
+    pub fn to_hdr_compressed(&self) -> Result<Vec<u8>, Error> {
+        compress(&self.to_hdr()?)
+    }
+
+    /// Encodes the histogram in the compressed HdrHistogram V2 format as
+    /// base64, as used in HdrHistogram interval logs. See
+    /// [`to_hdr`](Histogram::to_hdr) for details.
+    pub fn to_hdr_base64(&self) -> Result<String, Error> {
+        Ok(STANDARD.encode(self.to_hdr_compressed()?))
+    }
+
+    /// Decodes a histogram from the HdrHistogram V2 format, either
+    /// uncompressed or compressed with DEFLATE.
+    ///
+    /// The grouping power is taken from the number of significant digits,
+    /// see the table in the [crate documentation](crate#hdrhistogram), and
+    /// the max value power from the highest trackable value. Every bucket
+    /// has the same bounds as the HdrHistogram bucket it was decoded from,
+    /// so the conversion is exact.
+    ///
+    /// Returns an error if:
+    /// - the bytes are not a valid V2 encoding (`InvalidEncoding`)
+    /// - the lowest discernible value is above one, and a non-zero bucket is
+    ///   wider than the bucket here with the same lower bound, which occurs
+    ///   for small values (`PrecisionLoss`)
+    pub fn from_hdr(bytes: &[u8]) -> Result<Self, Error> {
+        decode(bytes).map(|histogram| Histogram::from(&histogram))
+    }
+
+    /// Decodes a histogram from the compressed HdrHistogram V2 format encoded
+    /// as base64. See [`from_hdr`](Histogram::from_hdr) for details.
+    pub fn from_hdr_base64(encoded: &str) -> Result<Self, Error> {
+        let bytes = STANDARD
+            .decode(encoded.trim())
+            .map_err(|_| Error::InvalidEncoding)?;
+
+        Self::from_hdr(&bytes)
+    }
+}
+
+impl SparseHistogram {
+    /// Encodes the histogram in the uncompressed HdrHistogram V2 format. See
+    /// [`Histogram::to_hdr`] for details.
+    pub fn to_hdr(&self) -> Result<Vec<u8>, Error> {
+        if self.overflow_count != 0 {
+            return Err(Error::OutOfRange);
+        }
+
+        let buckets = self
+            .index
+            .iter()
+            .zip(self.count.iter())
+            .map(|(index, count)| (*index as usize, *count));
+
+        encode(&self.config, buckets)
+    }
+
+    /// Encodes the histogram in the HdrHistogram V2 format, compressed with
+    /// DEFLATE. See [`Histogram::to_hdr`] for details.
+    pub fn to_hdr_compressed(&self) -> Result<Vec<u8>, Error> {
+        compress(&self.to_hdr()?)
+    }
+
+    /// Encodes the histogram in the compressed HdrHistogram V2 format as
+    /// base64. See [`Histogram::to_hdr`] for details.
+    pub fn to_hdr_base64(&self) -> Result<String, Error> {
+        Ok(STANDARD.encode(self.to_hdr_compressed()?))
+    }
+
+    /// Decodes a histogram from the HdrHistogram V2 format, either
+    /// uncompressed or compressed with DEFLATE. See [`Histogram::from_hdr`]
+    /// for details.
+    pub fn from_hdr(bytes: &[u8]) -> Result<Self, Error> {
+        decode(bytes)
+    }
+
+    /// Decodes a histogram from the compressed HdrHistogram V2 format encoded
+    /// as base64. See [`Histogram::from_hdr`] for details.
+    pub fn from_hdr_base64(encoded: &str) -> Result<Self, Error> {
+        let bytes = STANDARD
+            .decode(encoded.trim())
+            .map_err(|_| Error::InvalidEncoding)?;
+
+        Self::from_hdr(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RangePolicy;
+    use hdrhistogram::serialization::{Deserializer, Serializer, V2DeflateSerializer};
+
+    #[test]
+    fn varint() {
+        for value in [0, 1, -1, 63, -64, 64, 1 << 20, i64::MAX, i64::MIN] {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, value);
+            assert!(buf.len() <= 9);
+            let mut slice = buf.as_slice();
+            assert_eq!(read_varint(&mut slice), Ok(value));
+            assert!(slice.is_empty());
+        }
+    }
+
+    #[test]
+    fn magnitude() {
+        let powers: Vec<u8> = (0..=MAX_DIGITS)
+            .map(|d| sub_bucket_magnitude(d) - 1)
+            .collect();
+        assert_eq!(powers, vec![0, 4, 7, 10, 14, 17]);
+    }
+
+    #[test]
+    // Tests that exported histograms are read by the reference implementation
+    fn export() {
+        for (grouping_power, exported) in [(2, 4), (7, 7), (10, 10), (17, 17)] {
+            let mut histogram = Histogram::new(grouping_power, 40).unwrap();
+            for value in (1..1_000_000).step_by(997) {
+                histogram.increment(value).unwrap();
+            }
+
+            let bytes = histogram.to_hdr_compressed().unwrap();
+            let hdr: hdrhistogram::Histogram<u64> = Deserializer::new()
+                .deserialize(&mut bytes.as_slice())
+                .unwrap();
+
+            assert_eq!(hdr.len(), histogram.as_slice().iter().sum::<u64>());
+            for bucket in histogram.iter().filter(|b| b.count() != 0) {
+                assert_eq!(
+                    hdr.count_between(bucket.start(), bucket.end()),
+                    bucket.count()
+                );
+                assert_eq!(hdr.highest_equivalent(bucket.end()), bucket.end());
+            }
+
+            // round trip through the uncompressed and base64 encodings
+            let decoded = Histogram::from_hdr(&histogram.to_hdr().unwrap()).unwrap();
+            assert_eq!(decoded.config().grouping_power(), exported);
+            let decoded =
+                SparseHistogram::from_hdr_base64(&histogram.to_hdr_base64().unwrap()).unwrap();
+            for percentile in [0.0, 0.5, 0.99, 1.0] {
+                assert_eq!(
+                    decoded.percentile(percentile).unwrap().unwrap().end(),
+                    histogram.percentile(percentile).unwrap().unwrap().end()
+                );
+            }
+        }
+    }
+
+    #[test]
+    // Tests that histograms from the reference implementation are imported
+    fn import() {
+        let mut hdr = hdrhistogram::Histogram::<u64>::new_with_bounds(1, 3_600_000_000, 3).unwrap();
+        for value in (1..10_000_000).step_by(7919) {
+            hdr.record(value).unwrap();
+        }
+        hdr.record_n(3_000_000_000, 5).unwrap();
+
+        let mut bytes = Vec::new();
+        V2DeflateSerializer::new()
+            .serialize(&hdr, &mut bytes)
+            .unwrap();
+
+        let histogram = SparseHistogram::from_hdr(&bytes).unwrap();
+        assert_eq!(histogram.config().grouping_power(), 10);
+        assert_eq!(histogram.config().max_value_power(), 32);
+        assert_eq!(histogram.count().iter().sum::<u64>(), hdr.len());
+        for bucket in &histogram {
+            assert_eq!(
+                hdr.count_between(bucket.start(), bucket.end()),
+                bucket.count()
+            );
+            assert_eq!(hdr.lowest_equivalent(bucket.end()), bucket.start());
+            assert_eq!(hdr.highest_equivalent(bucket.start()), bucket.end());
+        }
+
+        let histogram = Histogram::from(&histogram);
+        assert_eq!(histogram.to_hdr_compressed().map(|b| b.len() > 8), Ok(true));
+    }
+
+    #[test]
+    // Tests that a unit magnitude is supported unless it makes buckets wider
+    fn unit_magnitude() {
+        let mut hdr = hdrhistogram::Histogram::<u64>::new_with_bounds(1024, 1 << 40, 2).unwrap();
+        hdr.record(1 << 30).unwrap();
+
+        let mut bytes = Vec::new();
+        V2DeflateSerializer::new()
+            .serialize(&hdr, &mut bytes)
+            .unwrap();
+        let histogram = Histogram::from_hdr(&bytes).unwrap();
+        let bucket = histogram.percentile(1.0).unwrap().unwrap();
+        assert_eq!(bucket.start(), hdr.lowest_equivalent(1 << 30));
+        assert_eq!(bucket.end(), hdr.highest_equivalent(1 << 30));
+
+        hdr.record(5000).unwrap();
+        let mut bytes = Vec::new();
+        V2DeflateSerializer::new()
+            .serialize(&hdr, &mut bytes)
+            .unwrap();
+        assert_eq!(Histogram::from_hdr(&bytes), Err(Error::PrecisionLoss));
+    }
+
+    #[test]
+    fn errors() {
+        let mut histogram = Histogram::new(18, 64).unwrap();
+        histogram.increment(1).unwrap();
+        assert_eq!(histogram.to_hdr(), Err(Error::PrecisionLoss));
+
+        let mut histogram = Histogram::new(7, 64).unwrap();
+        histogram.increment(u64::MAX).unwrap();
+        assert_eq!(histogram.to_hdr(), Err(Error::OutOfRange));
+
+        let mut histogram = Histogram::new(7, 64).unwrap();
+        histogram.add(1, u64::MAX).unwrap();
+        assert_eq!(histogram.to_hdr(), Err(Error::Overflow));
+
+        let mut histogram = Histogram::new(7, 32)
+            .unwrap()
+            .with_range_policy(RangePolicy::Count);
+        histogram.increment(u64::MAX).unwrap();
+        assert_eq!(histogram.to_hdr(), Err(Error::OutOfRange));
+        assert_eq!(
+            SparseHistogram::from(&histogram).to_hdr(),
+            Err(Error::OutOfRange)
+        );
+
+        assert_eq!(Histogram::from_hdr(&[]), Err(Error::InvalidEncoding));
+        assert_eq!(
+            Histogram::from_hdr_base64("not base64!"),
+            Err(Error::InvalidEncoding)
+        );
+
+        let mut histogram = Histogram::new(7, 64).unwrap();
+        histogram.increment(100).unwrap();
+        let bytes = histogram.to_hdr_compressed().unwrap();
+        assert_eq!(
+            Histogram::from_hdr(&bytes[..bytes.len() - 1]),
+            Err(Error::InvalidEncoding)
+        );
+        let bytes = histogram.to_hdr().unwrap();
+        assert_eq!(
+            Histogram::from_hdr(&bytes[..HEADER_LEN - 1]),
+            Err(Error::InvalidEncoding)
+        );
+
+        // decompression stops at the end of the payload
+        let mut trailing = bytes.clone();
+        trailing.resize(trailing.len() + (1 << 20), 0);
+        assert_eq!(
+            Histogram::from_hdr(&compress(&trailing).unwrap()),
+            Histogram::from_hdr(&bytes)
+        );
+
+        // a payload longer than any valid one is rejected before it is
+        // decompressed
+        let mut huge = bytes;
+        huge[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(
+            Histogram::from_hdr(&compress(&huge).unwrap()),
+            Err(Error::InvalidEncoding)
+        );
+    }
+}
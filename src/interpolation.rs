@@ -0,0 +1,184 @@
+use crate::{Bucket, Error};
+
+/// Selects how a single value is estimated for a percentile from the bucket
+/// which contains it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Report the inclusive lower bound of the bucket.
+    Lower,
+    /// Report the inclusive upper bound of the bucket. This matches the
+    /// "highest equivalent value" reported by HdrHistogram.
+    Upper,
+    /// Report the midpoint of the bucket.
+    Midpoint,
+    /// Linearly interpolate within the bucket based on where the target rank
+    /// falls among the observations in the bucket. This matches the
+    /// `histogram_quantile` function of Prometheus and produces smooth
+    /// estimates instead of steps at bucket boundaries.
+    #[default]
+    Linear,
+}
+
+impl Interpolation {
+    /// Returns the estimated value for a percentile with the fractional
+    /// `rank` which falls within the `bucket`, given the number of
+    /// observations `below` the bucket.
+    pub(crate) fn value(&self, bucket: &Bucket, below: u128, rank: f64) -> f64 {
+        let start = bucket.start() as f64;
+        let end = bucket.end() as f64;
+
+        match self {
+            Self::Lower => start,
+            Self::Upper => end,
+            Self::Midpoint => (start + end) / 2.0,
+            Self::Linear => {
+                let fraction = ((rank - below as f64) / bucket.count() as f64).clamp(0.0, 1.0);
+                start + fraction * (end - start)
+            }
+        }
+    }
+}
+
+/// Estimates a single value for each percentile from the buckets of a
+/// histogram. The results are sorted by the percentile. Returns `None` if the
+/// histogram is empty.
+pub(crate) fn percentile_values<'a, T>(
+    histogram: &'a T,
+    percentiles: &[f64],
+    interpolation: Interpolation,
+) -> Result<Option<Vec<(f64, f64)>>, Error>
+where
+    &'a T: IntoIterator<Item = Bucket>,
+{
+    // validate all the percentiles
+    for percentile in percentiles {
+        if !(0.0..=1.0).contains(percentile) {
+            return Err(Error::InvalidPercentile);
+        }
+    }
+
+    // get the total count
+    let total_count: u128 = histogram
+        .into_iter()
+        .map(|bucket| bucket.count() as u128)
+        .sum();
+
+    // empty histogram, no percentiles available
+    if total_count == 0 {
+        return Ok(None);
+    }
+
+    // sort the requested percentiles so we can find them in a single pass
+    let mut percentiles = percentiles.to_vec();
+    percentiles.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let mut buckets = histogram.into_iter().filter(|bucket| bucket.count() != 0);
+    let mut bucket = buckets.next();
+    let mut below: u128 = 0;
+
+    let result = percentiles
+        .iter()
+        .filter_map(|percentile| {
+            let rank = percentile * total_count as f64;
+
+            // For 0.0 percentile (min) we need to report the first bucket
+            // with a non-zero count.
+            let count = std::cmp::max(1, rank.ceil() as u128);
+
+            loop {
+                let current = bucket.as_ref()?;
+
+                // found the matching bucket for this percentile
+                if below + current.count() as u128 >= count {
+                    return Some((*percentile, interpolation.value(current, below, rank)));
+                }
+
+                below += current.count() as u128;
+                bucket = buckets.next();
+            }
+        })
+        .collect();
+
+    Ok(Some(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Histogram, SparseHistogram};
+
+    #[test]
+    fn interpolation() {
+        let mut histogram = Histogram::new(2, 16).unwrap();
+        assert_eq!(
+            histogram.percentile_value(0.5, Interpolation::Linear),
+            Ok(None)
+        );
+
+        // a single bucket covering 64..=79
+        let _ = histogram.add(70, 100);
+
+        assert_eq!(
+            histogram.percentile_value(0.5, Interpolation::Lower),
+            Ok(Some(64.0))
+        );
+        assert_eq!(
+            histogram.percentile_value(0.5, Interpolation::Upper),
+            Ok(Some(79.0))
+        );
+        assert_eq!(
+            histogram.percentile_value(0.5, Interpolation::Midpoint),
+            Ok(Some(71.5))
+        );
+        assert_eq!(
+            histogram.percentile_value(0.5, Interpolation::Linear),
+            Ok(Some(71.5))
+        );
+        assert_eq!(
+            histogram.percentile_value(0.0, Interpolation::Linear),
+            Ok(Some(64.0))
+        );
+        assert_eq!(
+            histogram.percentile_value(1.0, Interpolation::Linear),
+            Ok(Some(79.0))
+        );
+
+        assert_eq!(
+            histogram.percentile_value(1.01, Interpolation::Linear),
+            Err(Error::InvalidPercentile)
+        );
+    }
+
+    #[test]
+    fn percentile_values() {
+        let mut histogram = Histogram::new(7, 64).unwrap();
+        for value in 1..=100 {
+            let _ = histogram.increment(value);
+        }
+        let _ = histogram.increment(1024);
+
+        let values = histogram
+            .percentile_values(&[0.99, 0.5, 0.999], Interpolation::Upper)
+            .unwrap()
+            .unwrap();
+        let buckets = histogram.percentiles(&[0.99, 0.5, 0.999]).unwrap().unwrap();
+        assert_eq!(values.len(), buckets.len());
+        for ((p1, v), (p2, b)) in values.iter().zip(buckets.iter()) {
+            assert_eq!(p1, p2);
+            assert_eq!(*v, b.end() as f64);
+        }
+
+        let sparse = SparseHistogram::from(&histogram);
+        for interpolation in [
+            Interpolation::Lower,
+            Interpolation::Upper,
+            Interpolation::Midpoint,
+            Interpolation::Linear,
+        ] {
+            assert_eq!(
+                sparse.percentile_values(&[0.25, 0.5, 1.0], interpolation),
+                histogram.percentile_values(&[0.25, 0.5, 1.0], interpolation)
+            );
+        }
+    }
+}
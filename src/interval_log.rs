@@ -0,0 +1,499 @@
+//! Reading and writing HdrHistogram interval logs, which store a series of
+//! timestamped histograms as lines of text with base64 encoded V2
+//! histograms.
+
+use crate::Histogram;
+use std::io::{self, BufRead, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const VERSION: &str = "#[Histogram log format version 1.3]";
+const START_TIME: &str = "#[StartTime: ";
+const BASE_TIME: &str = "#[BaseTime: ";
+const LEGEND: &str =
+    "\"StartTimestamp\",\"Interval_Length\",\"Interval_Max\",\"Interval_Compressed_Histogram\"";
+const TAG: &str = "Tag=";
+
+/// Timestamps more than this many seconds before the start time are taken to
+/// be relative to the start time, following the reference implementation.
+const YEAR: f64 = 365.0 * 24.0 * 3600.0;
+
+fn to_seconds(time: SystemTime) -> f64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs_f64(),
+        Err(e) => -e.duration().as_secs_f64(),
+    }
+}
+
+fn from_seconds(seconds: f64) -> Option<SystemTime> {
+    let duration = Duration::try_from_secs_f64(seconds.abs()).ok()?;
+
+    if seconds >= 0.0 {
+        UNIX_EPOCH.checked_add(duration)
+    } else {
+        UNIX_EPOCH.checked_sub(duration)
+    }
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// A histogram for a single interval of an interval log.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IntervalHistogram {
+    tag: Option<String>,
+    start: SystemTime,
+    length: Duration,
+    histogram: Histogram,
+}
+
+impl IntervalHistogram {
+    /// Returns the tag of the interval, if any.
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+
+    /// Returns the start time of the interval.
+    pub fn start(&self) -> SystemTime {
+        self.start
+    }
+
+    /// Returns the length of the interval.
+    pub fn length(&self) -> Duration {
+        self.length
+    }
+
+    /// Returns the histogram of the values recorded during the interval.
+    pub fn histogram(&self) -> &Histogram {
+        &self.histogram
+    }
+
+    /// Consumes the interval, returning its histogram.
+    pub fn into_histogram(self) -> Histogram {
+        self.histogram
+    }
+}
+
+/// Reads the interval histograms from an HdrHistogram interval log.
+///
+/// Interval timestamps are resolved against the `BaseTime` header if
+/// present. Otherwise, like the reference implementation, timestamps more
+/// than a year before the `StartTime` header are taken as relative to the
+/// start time, and any others as seconds since the epoch.
+///
+/// Each interval is decoded with [`Histogram::from_hdr`]. Malformed lines are
+/// returned as errors of kind [`io::ErrorKind::InvalidData`].
+#[derive(Debug)]
+pub struct IntervalLogReader<R> {
+    reader: R,
+    line: String,
+    start_time: Option<f64>,
+    base_time: Option<f64>,
+}
+
+impl<R: BufRead> IntervalLogReader<R> {
+    /// Creates a new reader for the interval log.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+            start_time: None,
+            base_time: None,
+        }
+    }
+
+    /// Returns the start time of the log, if it has been read.
+    pub fn start_time(&self) -> Option<SystemTime> {
+        self.start_time.and_then(from_seconds)
+    }
+
+    /// Returns the time which interval timestamps are relative to, once it is
+    /// known.
+    pub fn base_time(&self) -> Option<SystemTime> {
+        self.base_time.and_then(from_seconds)
+    }
+
+    /// Parses the seconds from a `StartTime` or `BaseTime` header.
+    fn parse_time(header: &str) -> io::Result<f64> {
+        header
+            .split_whitespace()
+            .next()
+            .and_then(|seconds| seconds.parse().ok())
+            .ok_or_else(|| invalid_data("invalid time in header"))
+    }
+
+    fn parse_interval(&mut self) -> io::Result<IntervalHistogram> {
+        let line = self.line.trim_end();
+
+        let (tag, line) = match line.strip_prefix(TAG) {
+            Some(line) => {
+                let (tag, line) = line
+                    .split_once(',')
+                    .ok_or_else(|| invalid_data("missing interval fields"))?;
+                (Some(tag.to_string()), line)
+            }
+            None => (None, line),
+        };
+
+        let mut fields = line.splitn(4, ',');
+        let mut seconds = || -> io::Result<f64> {
+            fields
+                .next()
+                .and_then(|field| field.trim().parse().ok())
+                .ok_or_else(|| invalid_data("invalid interval field"))
+        };
+        let timestamp = seconds()?;
+        let length = seconds()?;
+        let _max = seconds()?;
+        let encoded = fields
+            .next()
+            .ok_or_else(|| invalid_data("missing interval histogram"))?;
+
+        let base_time = *self.base_time.get_or_insert(match self.start_time {
+            Some(start_time) if timestamp < start_time - YEAR => start_time,
+            _ => 0.0,
+        });
+
+        let start = from_seconds(base_time + timestamp)
+            .ok_or_else(|| invalid_data("invalid interval timestamp"))?;
+        let length = Duration::try_from_secs_f64(length).map_err(invalid_data)?;
+        let histogram = Histogram::from_hdr_base64(encoded).map_err(invalid_data)?;
+
+        Ok(IntervalHistogram {
+            tag,
+            start,
+            length,
+            histogram,
+        })
+    }
+}
+
+impl<R: BufRead> Iterator for IntervalLogReader<R> {
+    type Item = io::Result<IntervalHistogram>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e)),
+            }
+
+            let line = self.line.trim();
+            if let Some(header) = line.strip_prefix(START_TIME) {
+                match Self::parse_time(header) {
+                    Ok(time) => self.start_time = Some(time),
+                    Err(e) => return Some(Err(e)),
+                }
+            } else if let Some(header) = line.strip_prefix(BASE_TIME) {
+                match Self::parse_time(header) {
+                    Ok(time) => self.base_time = Some(time),
+                    Err(e) => return Some(Err(e)),
+                }
+            } else if line.is_empty()
+                || line.starts_with('#')
+                || line.starts_with("\"StartTimestamp\"")
+                || line.starts_with("StartTimestamp")
+            {
+                continue;
+            } else {
+                return Some(self.parse_interval());
+            }
+        }
+    }
+}
+
+/// Writes histograms to an HdrHistogram interval log.
+///
+/// The log starts with a header which records the start time, and interval
+/// timestamps are written relative to it. Each histogram is encoded with
+/// [`Histogram::to_hdr_base64`].
+///
+/// ```
+/// use histogram::{AtomicHistogram, IntervalLogReader, IntervalLogWriter};
+/// use std::time::{Duration, SystemTime};
+///
+/// let start = SystemTime::now();
+/// let histogram = AtomicHistogram::new(7, 64).unwrap();
+///
+/// let mut writer = IntervalLogWriter::new(Vec::new(), start).unwrap();
+/// histogram.increment(42).unwrap();
+/// writer
+///     .write(&histogram.load(), start, Duration::from_secs(1), Some("reads"))
+///     .unwrap();
+///
+/// let log = writer.into_inner();
+/// let mut reader = IntervalLogReader::new(log.as_slice());
+/// let interval = reader.next().unwrap().unwrap();
+/// assert_eq!(interval.tag(), Some("reads"));
+/// assert_eq!(interval.length(), Duration::from_secs(1));
+/// ```
+#[derive(Debug)]
+pub struct IntervalLogWriter<W: Write> {
+    writer: W,
+    base_time: f64,
+    max_value_divisor: f64,
+}
+
+impl<W: Write> IntervalLogWriter<W> {
+    /// Creates a new writer and writes the log header, using `start_time` as
+    /// both the start time and base time of the log.
+    pub fn new(mut writer: W, start_time: SystemTime) -> io::Result<Self> {
+        let seconds = to_seconds(start_time);
+
+        writeln!(writer, "{VERSION}")?;
+        writeln!(writer, "{START_TIME}{seconds:.3} (seconds since epoch)]")?;
+        writeln!(writer, "{BASE_TIME}{seconds:.3} (seconds since epoch)]")?;
+        writeln!(writer, "{LEGEND}")?;
+
+        Ok(Self {
+            writer,
+            base_time: seconds,
+            max_value_divisor: 1.0,
+        })
+    }
+
+    /// Sets the divisor applied to the maximum value written for each
+    /// interval, which is informational. For example, use `1e6` to write the
+    /// maximum in milliseconds for histograms recorded in nanoseconds, as the
+    /// reference implementation does by default.
+    pub fn with_max_value_divisor(mut self, divisor: f64) -> Self {
+        self.max_value_divisor = divisor;
+        self
+    }
+
+    /// Writes a comment, which may span several lines.
+    pub fn write_comment(&mut self, comment: &str) -> io::Result<()> {
+        for line in comment.lines() {
+            writeln!(self.writer, "#{line}")?;
+        }
+        Ok(())
+    }
+
+    /// Writes the histogram for the interval starting at `start` which lasted
+    /// for `length`, with an optional tag.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if the tag
+    /// contains a comma or whitespace, or if the histogram cannot be
+    /// encoded. See [`Histogram::to_hdr`].
+    pub fn write(
+        &mut self,
+        histogram: &Histogram,
+        start: SystemTime,
+        length: Duration,
+        tag: Option<&str>,
+    ) -> io::Result<()> {
+        if tag.is_some_and(|tag| tag.contains(|c: char| c == ',' || c.is_whitespace())) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid tag"));
+        }
+
+        let encoded = histogram
+            .to_hdr_base64()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let max = match histogram.summary().and_then(|s| s.max()) {
+            Some(max) => max,
+            None => histogram
+                .iter()
+                .filter(|bucket| bucket.count() != 0)
+                .last()
+                .map(|bucket| bucket.end())
+                .unwrap_or(0),
+        };
+
+        if let Some(tag) = tag {
+            write!(self.writer, "{TAG}{tag},")?;
+        }
+        writeln!(
+            self.writer,
+            "{:.3},{:.3},{:.3},{}",
+            to_seconds(start) - self.base_time,
+            length.as_secs_f64(),
+            max as f64 / self.max_value_divisor,
+            encoded
+        )
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Consumes the log writer, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hdrhistogram::serialization::interval_log::{
+        IntervalLogIterator, IntervalLogWriterBuilder, LogEntry, Tag,
+    };
+    use hdrhistogram::serialization::{Deserializer, V2DeflateSerializer};
+
+    fn histogram(values: &[u64]) -> Histogram {
+        let mut histogram = Histogram::new(7, 32).unwrap();
+        for value in values {
+            histogram.increment(*value).unwrap();
+        }
+        histogram
+    }
+
+    #[test]
+    fn round_trip() {
+        let start = UNIX_EPOCH + Duration::from_millis(1_500_000_040_000);
+        let second = Duration::from_secs(1);
+
+        let mut writer = IntervalLogWriter::new(Vec::new(), start).unwrap();
+        writer.write_comment("first line\nsecond line").unwrap();
+        writer
+            .write(&histogram(&[1, 2, 3]), start, second, None)
+            .unwrap();
+        writer
+            .write(&histogram(&[100]), start + second, second, Some("b"))
+            .unwrap();
+        assert!(
+            writer
+                .write(&histogram(&[]), start, second, Some("a b"))
+                .is_err()
+        );
+        let log = writer.into_inner();
+
+        let mut reader = IntervalLogReader::new(log.as_slice());
+        let first = reader.next().unwrap().unwrap();
+        assert_eq!(reader.start_time(), Some(start));
+        assert_eq!(reader.base_time(), Some(start));
+        assert_eq!(first.tag(), None);
+        assert_eq!(first.start(), start);
+        assert_eq!(first.length(), second);
+        assert_eq!(first.histogram().percentile(1.0).unwrap().unwrap().end(), 3);
+
+        let next = reader.next().unwrap().unwrap();
+        assert_eq!(next.tag(), Some("b"));
+        assert_eq!(next.start(), start + second);
+        assert_eq!(
+            next.into_histogram()
+                .percentile(0.0)
+                .unwrap()
+                .unwrap()
+                .end(),
+            100
+        );
+
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    // Tests that logs written here are read by the reference implementation
+    fn export() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+        let mut writer = IntervalLogWriter::new(Vec::new(), start)
+            .unwrap()
+            .with_max_value_divisor(10.0);
+        writer
+            .write(
+                &histogram(&[5, 10, 1000]),
+                start + Duration::from_millis(2500),
+                Duration::from_secs(1),
+                Some("tagged"),
+            )
+            .unwrap();
+        let log = writer.into_inner();
+
+        let entries: Vec<_> = IntervalLogIterator::new(&log)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert!(matches!(entries[0], LogEntry::StartTime(t) if t.as_secs() == 1_500_000_000));
+        assert!(matches!(entries[1], LogEntry::BaseTime(t) if t.as_secs() == 1_500_000_000));
+        match &entries[2] {
+            LogEntry::Interval(interval) => {
+                assert_eq!(interval.tag().map(|t| t.as_str()), Some("tagged"));
+                assert_eq!(interval.start_timestamp(), Duration::from_millis(2500));
+                assert_eq!(interval.max(), 100.3);
+
+                let bytes = base64::Engine::decode(
+                    &base64::engine::general_purpose::STANDARD,
+                    interval.encoded_histogram(),
+                )
+                .unwrap();
+                let hdr: hdrhistogram::Histogram<u64> = Deserializer::new()
+                    .deserialize(&mut bytes.as_slice())
+                    .unwrap();
+                assert_eq!(hdr.len(), 3);
+            }
+            _ => panic!("expected an interval"),
+        }
+    }
+
+    #[test]
+    // Tests that logs from the reference implementation are read
+    fn import() {
+        let base = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+        let mut hdr = hdrhistogram::Histogram::<u64>::new_with_bounds(1, 1 << 30, 2).unwrap();
+        hdr.record(42).unwrap();
+
+        let mut log = Vec::new();
+        let mut serializer = V2DeflateSerializer::new();
+        let mut writer = IntervalLogWriterBuilder::new()
+            .add_comment("generated")
+            .with_start_time(base)
+            .with_base_time(base)
+            .begin_log_with(&mut log, &mut serializer)
+            .unwrap();
+        writer
+            .write_histogram(
+                &hdr,
+                Duration::from_secs(10),
+                Duration::from_secs(5),
+                Tag::new("x"),
+            )
+            .unwrap();
+
+        let intervals: Vec<_> = IntervalLogReader::new(log.as_slice())
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(intervals.len(), 1);
+        assert_eq!(intervals[0].tag(), Some("x"));
+        assert_eq!(intervals[0].start(), base + Duration::from_secs(10));
+        assert_eq!(intervals[0].length(), Duration::from_secs(5));
+        let bucket = intervals[0].histogram().percentile(0.5).unwrap().unwrap();
+        assert_eq!(bucket.end(), hdr.highest_equivalent(42));
+    }
+
+    #[test]
+    // Tests the resolution of timestamps without a base time
+    fn timestamps() {
+        let encoded = histogram(&[1]).to_hdr_base64().unwrap();
+
+        let log =
+            format!("#[StartTime: 1500000000.000 (seconds since epoch)]\n2.5,1.0,1.0,{encoded}\n");
+        let interval = IntervalLogReader::new(log.as_bytes())
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            interval.start(),
+            UNIX_EPOCH + Duration::from_millis(1_500_000_002_500)
+        );
+
+        let log = format!("1500000002.500,1.0,1.0,{encoded}\n");
+        let interval = IntervalLogReader::new(log.as_bytes())
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            interval.start(),
+            UNIX_EPOCH + Duration::from_millis(1_500_000_002_500)
+        );
+
+        let log = "1.0,1.0,1.0,not-base64\n";
+        let error = IntervalLogReader::new(log.as_bytes()).next().unwrap();
+        assert_eq!(error.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let log = "1.0,oops\n";
+        let error = IntervalLogReader::new(log.as_bytes()).next().unwrap();
+        assert_eq!(error.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
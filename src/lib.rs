@@ -10,8 +10,36 @@
 //! - [`AtomicHistogram`] — atomic histogram for concurrent recording. Take a
 //!   snapshot with [`AtomicHistogram::load`] or [`AtomicHistogram::drain`] to
 //!   query percentiles.
+//! - [`ShardedAtomicHistogram`] — atomic histogram striped across several
+//!   shards. Use when many threads record concurrently into hot buckets.
+//! - [`BufferedRecorder`] — thread-local buffers which flush into a shared
+//!   [`AtomicHistogram`]. Use when even an atomic add per value is too costly.
 //! - [`SparseHistogram`] — compact representation storing only non-zero
-//!   buckets. Useful for serialization and storage.
+//!   buckets. Useful for serialization and storage, with a compact binary
+//!   encoding via [`SparseHistogram::to_bytes`].
+//! - [`SparseHistogramView`] — read-only view over the binary encoding of a
+//!   sparse histogram. Use for querying stored histograms without copying.
+//! - [`SignedHistogram`] — histogram of `i64` values, with mirrored buckets
+//!   for the negative and positive values. Use for recording offsets and
+//!   deltas. [`SparseSignedHistogram`] stores only its non-zero buckets.
+//! - [`FloatHistogram`] — histogram of non-negative `f64` values, bucketed by
+//!   exponent and mantissa as configured by a [`FloatConfig`]. Use for ratios
+//!   and utilizations.
+//! - [`DurationHistogram`] — histogram of [`Duration`](std::time::Duration)
+//!   values recorded at a chosen [`TimeUnit`]. Use for latencies.
+//!   [`AtomicDurationHistogram`] allows concurrent recording.
+//! - [`CumulativeHistogram`] — read-only snapshot storing cumulative counts.
+//!   Use for answering many percentile or rank queries on the same data.
+//! - [`WindowedHistogram`] — sliding window over a fixed number of intervals.
+//!   Use for percentiles over recent data, such as the last minute.
+//! - [`AtomicWindowedHistogram`] — sliding window with concurrent recording.
+//!   Rotate with [`AtomicWindowedHistogram::rotate`] from a reporting thread.
+//! - [`DecayingHistogram`] — exponentially decaying weights with a configurable
+//!   half-life. Use for percentiles biased towards recent observations.
+//! - [`ExponentialHistogram`] — OpenTelemetry exponential histogram data
+//!   point. Use for exporting histograms over OTLP.
+//! - [`NativeHistogram`] — Prometheus native histogram with spans and delta
+//!   encoded counts. Use for exporting high resolution histograms.
 //!
 //! # Example
 //!
@@ -35,19 +63,95 @@
 //! println!("p99: {}-{}", p99.start(), p99.end());
 //! ```
 //!
+//! # HdrHistogram
+//!
+//! With the `hdr` feature, [`Histogram`] and [`SparseHistogram`] can be
+//! converted to and from the HdrHistogram V2 encoding, optionally compressed
+//! with DEFLATE and encoded as base64, which is understood by the
+//! HdrHistogram libraries and the tools built on them. Series of histograms
+//! can be stored in HdrHistogram interval logs with [`IntervalLogWriter`] and
+//! read back with [`IntervalLogReader`].
+//!
+//! HdrHistogram derives its bucket layout from a number of significant
+//! digits. Each layout matches a grouping power exactly:
+//!
+//! | significant digits | grouping power | relative error |
+//! |--------------------|----------------|----------------|
+//! | 0                  | 0              | 100%           |
+//! | 1                  | 4              | 6.25%          |
+//! | 2                  | 7              | 0.78%          |
+//! | 3                  | 10             | 0.098%         |
+//! | 4                  | 14             | 0.0061%        |
+//! | 5                  | 17             | 0.00076%       |
+//!
+//! Other grouping powers up to 17 are exported using the next more precise
+//! layout, which subdivides each bucket. Exported counts keep the upper bound
+//! of their bucket, and imported buckets keep their bounds exactly, so in
+//! both directions every reported value is within the relative error of the
+//! original histogram. Conversions which cannot meet this guarantee return
+//! [`Error::PrecisionLoss`].
+//!
 //! # Background
 //! Please see: <https://h2histogram.org>
 
 mod atomic;
+mod atomic_window;
+mod binary;
 mod bucket;
 mod config;
+mod correction;
+mod counter;
+mod cumulative;
+mod decaying;
+mod duration;
 mod errors;
+mod exponential;
+mod float;
+#[cfg(feature = "hdr")]
+mod hdr;
+mod interpolation;
+#[cfg(feature = "hdr")]
+mod interval_log;
+mod merge;
+mod native;
+mod prometheus;
+mod range;
+mod rank;
+mod recorder;
+mod resize;
+mod sharded;
+mod signed;
 mod sparse;
 mod standard;
+mod statistics;
+mod summary;
+mod view;
+mod window;
 
 pub use atomic::AtomicHistogram;
+pub use atomic_window::AtomicWindowedHistogram;
 pub use bucket::Bucket;
 pub use config::Config;
+pub use counter::CounterPolicy;
+pub use cumulative::CumulativeHistogram;
+pub use decaying::DecayingHistogram;
+pub use duration::{AtomicDurationHistogram, DurationHistogram, TimeUnit};
 pub use errors::Error;
+pub use exponential::{ExponentialBuckets, ExponentialHistogram};
+pub use float::{FloatConfig, FloatHistogram};
+pub use interpolation::Interpolation;
+#[cfg(feature = "hdr")]
+pub use interval_log::{IntervalHistogram, IntervalLogReader, IntervalLogWriter};
+pub use native::{BucketSpan, NativeHistogram};
+pub use range::RangePolicy;
+pub use rank::Rank;
+pub use recorder::{BufferedRecorder, LocalRecorder};
+pub use resize::TruncationPolicy;
+pub use sharded::ShardedAtomicHistogram;
+pub use signed::{SignedHistogram, SparseSignedHistogram};
 pub use sparse::SparseHistogram;
 pub use standard::Histogram;
+pub use statistics::{Representative, Statistics};
+pub use summary::Summary;
+pub use view::SparseHistogramView;
+pub use window::WindowedHistogram;
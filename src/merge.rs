@@ -0,0 +1,265 @@
+//! Merging histograms with different configs into their coarsest common
+//! config.
+
+use crate::summary;
+use crate::{Config, CounterPolicy, Error, Histogram, RangePolicy, SparseHistogram};
+use std::collections::BTreeMap;
+
+/// Returns the config with the smaller grouping power and the smaller max
+/// value power of the two, which every bucket of both configs maps into.
+fn coarsest(a: &Config, b: &Config) -> Config {
+    // both grouping powers are below both max value powers, so this is valid
+    Config::new(
+        a.grouping_power().min(b.grouping_power()),
+        a.max_value_power().min(b.max_value_power()),
+    )
+    .unwrap()
+}
+
+/// Maps a bucket index from one config into a coarser config, returning
+/// `None` if the bucket is above the range of the coarser config.
+///
+/// As in downsampling, each bucket is contained by a bucket of the config
+/// with the smaller grouping power, so its lower bound finds that bucket.
+fn remap(from: &Config, to: &Config, index: usize) -> Option<usize> {
+    to.value_to_index(from.index_to_lower_bound(index)).ok()
+}
+
+/// Returns where the counts above the range of the result go: into the
+/// overflow count with [`RangePolicy::Count`], and otherwise clamped into the
+/// last bucket.
+fn clamp(config: &Config, policy: RangePolicy, index: Option<usize>) -> Option<usize> {
+    match policy {
+        RangePolicy::Count => index,
+        _ => index.or(Some(config.total_buckets() - 1)),
+    }
+}
+
+/// Adds the count to the counter according to the policy.
+fn add_to(policy: CounterPolicy, counter: &mut u64, count: u64) -> Result<(), Error> {
+    *counter = policy.add(*counter, count)?;
+    Ok(())
+}
+
+/// Adds the buckets of a histogram into a coarser config with `add`, which
+/// is given `None` for the buckets above its range, returning the total count
+/// of those buckets.
+fn merge_into<'a>(
+    from: &Config,
+    to: &Config,
+    histogram: impl Iterator<Item = (usize, &'a u64)>,
+    mut add: impl FnMut(Option<usize>, u64) -> Result<(), Error>,
+) -> Result<u64, Error> {
+    let mut clamped: u64 = 0;
+
+    for (index, count) in histogram.filter(|(_, count)| **count != 0) {
+        let index = remap(from, to, index);
+        add(index, *count)?;
+
+        // the total may not fit if the counter policy let the counts
+        // themselves overflow
+        if index.is_none() {
+            clamped = clamped.saturating_add(*count);
+        }
+    }
+
+    Ok(clamped)
+}
+
+impl Histogram {
+    /// Merges the other histogram with this histogram, even if their configs
+    /// differ, and returns the result as a new histogram along with the total
+    /// count which had to be clamped.
+    ///
+    /// The result uses the coarsest common config, with the smaller grouping
+    /// power and the smaller max value power of the two. Each bucket is mapped
+    /// into the bucket which contains it, as in
+    /// [`downsample`](Histogram::downsample). Counts in buckets above the max
+    /// value of the result are clamped into its last bucket, or are added to
+    /// the overflow count if the range policy of this histogram is
+    /// [`RangePolicy::Count`].
+    ///
+    /// The result keeps the range and counter policies of this histogram, and
+    /// the counts are added according to the [`CounterPolicy`], so an error is
+    /// only returned if it is checked and there is an overflow. The overflow
+    /// counts are added. The result only carries a summary if both histograms
+    /// have one, and with a checked policy if their merged count does not
+    /// overflow.
+    pub fn merge(&self, other: &Histogram) -> Result<(Histogram, u64), Error> {
+        let config = coarsest(&self.config, &other.config);
+        let policy = self.counter_policy;
+
+        let mut histogram = Histogram::with_config(&config);
+        let mut overflow_count = self.overflow_count;
+        add_to(policy, &mut overflow_count, other.overflow_count)?;
+        let mut add = |index, count| match clamp(&config, self.range_policy, index) {
+            Some(index) => add_to(policy, &mut histogram.buckets[index], count),
+            None => add_to(policy, &mut overflow_count, count),
+        };
+        let clamped = merge_into(
+            &self.config,
+            &config,
+            self.buckets.iter().enumerate(),
+            &mut add,
+        )? + merge_into(
+            &other.config,
+            &config,
+            other.buckets.iter().enumerate(),
+            &mut add,
+        )?;
+        histogram.summary = summary::merge(policy, self.summary(), other.summary()).map(Box::new);
+        histogram.range_policy = self.range_policy;
+        histogram.overflow_count = overflow_count;
+        histogram.counter_policy = policy;
+
+        Ok((histogram, clamped))
+    }
+}
+
+impl SparseHistogram {
+    /// Merges the other histogram with this histogram, even if their configs
+    /// differ, and returns the result as a new histogram along with the total
+    /// count which had to be clamped. See [`Histogram::merge`].
+    pub fn merge(&self, other: &SparseHistogram) -> Result<(SparseHistogram, u64), Error> {
+        let config = coarsest(&self.config, &other.config);
+        let policy = self.counter_policy;
+
+        let mut merged = BTreeMap::new();
+        let mut overflow_count = self.overflow_count;
+        add_to(policy, &mut overflow_count, other.overflow_count)?;
+        let mut add = |index, count| match clamp(&config, self.range_policy, index) {
+            Some(index) => add_to(policy, merged.entry(index).or_insert(0), count),
+            None => add_to(policy, &mut overflow_count, count),
+        };
+        let clamped = merge_into(
+            &self.config,
+            &config,
+            self.index.iter().map(|i| *i as usize).zip(&self.count),
+            &mut add,
+        )? + merge_into(
+            &other.config,
+            &config,
+            other.index.iter().map(|i| *i as usize).zip(&other.count),
+            &mut add,
+        )?;
+
+        // a wrapping counter can return to zero, and is then left out
+        merged.retain(|_, count| *count != 0);
+        let histogram = SparseHistogram {
+            config,
+            index: merged.keys().map(|index| *index as u32).collect(),
+            count: merged.into_values().collect(),
+            summary: summary::merge(policy, self.summary, other.summary),
+            range_policy: self.range_policy,
+            counter_policy: policy,
+            overflow_count,
+        };
+
+        Ok((histogram, clamped))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grouping_power() {
+        let mut a = Histogram::new(7, 20).unwrap();
+        let mut b = Histogram::new(4, 20).unwrap();
+        for value in [1, 100, 1000, 10_000] {
+            a.increment(value).unwrap();
+            b.increment(value).unwrap();
+        }
+
+        let (merged, clamped) = a.merge(&b).unwrap();
+        assert_eq!(clamped, 0);
+        assert_eq!(merged.config(), b.config());
+        assert_eq!(merged, b.checked_add(&a.downsample(4).unwrap()).unwrap());
+
+        // the same either way around
+        assert_eq!(b.merge(&a).unwrap(), (merged.clone(), 0));
+
+        let (sparse, clamped) = SparseHistogram::from(&a)
+            .merge(&SparseHistogram::from(&b))
+            .unwrap();
+        assert_eq!(clamped, 0);
+        assert_eq!(sparse, SparseHistogram::from(&merged));
+    }
+
+    #[test]
+    fn max_value_power() {
+        let mut a = Histogram::new(4, 10).unwrap();
+        let mut b = Histogram::new(4, 16).unwrap();
+        a.increment(1023).unwrap();
+        b.add(500, 2).unwrap();
+        b.add(1024, 3).unwrap();
+        b.add(60_000, 4).unwrap();
+
+        let (merged, clamped) = a.merge(&b).unwrap();
+        assert_eq!(merged.config(), a.config());
+        assert_eq!(clamped, 7);
+        assert_eq!(merged.as_slice().last(), Some(&8));
+        assert_eq!(merged.percentile(0.0).unwrap().unwrap().count(), 2);
+
+        let (sparse, clamped) = SparseHistogram::from(&a)
+            .merge(&SparseHistogram::from(&b))
+            .unwrap();
+        assert_eq!(clamped, 7);
+        assert_eq!(sparse, SparseHistogram::from(&merged));
+
+        // counted as overflow rather than clamped with the count policy
+        let a = a.with_range_policy(RangePolicy::Count);
+        let (merged, clamped) = a.merge(&b).unwrap();
+        assert_eq!(clamped, 7);
+        assert_eq!(merged.as_slice().last(), Some(&1));
+        assert_eq!(merged.overflow_count(), 7);
+
+        let (sparse, _) = SparseHistogram::from(&a)
+            .merge(&SparseHistogram::from(&b))
+            .unwrap();
+        assert_eq!(sparse, SparseHistogram::from(&merged));
+    }
+
+    #[test]
+    fn summary_and_overflow() {
+        let mut a = Histogram::new(7, 64).unwrap().with_summary();
+        let mut b = Histogram::new(3, 32).unwrap().with_summary();
+        a.increment(5).unwrap();
+        b.increment(7).unwrap();
+
+        let (merged, _) = a.merge(&b).unwrap();
+        let summary = merged.summary().unwrap();
+        assert_eq!((summary.min(), summary.max()), (Some(5), Some(7)));
+
+        a.add(5, u64::MAX - 1).unwrap();
+        b.increment(5).unwrap();
+        let (merged, _) = a.merge(&b).unwrap();
+        assert_eq!(merged.as_slice()[5], 0);
+    }
+
+    #[test]
+    // Tests that the counts are added according to the counter policy
+    fn counter_policy() {
+        let mut b = Histogram::new(3, 32).unwrap();
+        b.add(5, 2).unwrap();
+
+        for (policy, expected) in [
+            (CounterPolicy::Wrapping, Ok(0)),
+            (CounterPolicy::Saturating, Ok(u64::MAX)),
+            (CounterPolicy::Checked, Err(Error::Overflow)),
+        ] {
+            let mut a = Histogram::new(7, 64).unwrap().with_counter_policy(policy);
+            a.add(5, u64::MAX - 1).unwrap();
+
+            let merged = a.merge(&b).map(|(h, _)| h.as_slice()[5]);
+            assert_eq!(merged, expected);
+
+            // an empty bucket is left out of a sparse histogram
+            let sparse = SparseHistogram::from(&a)
+                .merge(&SparseHistogram::from(&b))
+                .map(|(h, _)| h.count().first().copied().unwrap_or(0));
+            assert_eq!(sparse, expected);
+        }
+    }
+}
@@ -0,0 +1,384 @@
+use crate::exponential::{self, ExponentialHistogram};
+use crate::statistics::Representative;
+use crate::{Bucket, Config, Error, Histogram, SparseHistogram, Summary};
+
+/// The schema range for exponential buckets in Prometheus.
+const MIN_SCHEMA: i32 = -4;
+const MAX_SCHEMA: i32 = 8;
+
+/// A run of consecutive buckets in a [`NativeHistogram`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct BucketSpan {
+    offset: i32,
+    length: u32,
+}
+
+impl BucketSpan {
+    /// Creates a span of `length` buckets. The offset of the first span is
+    /// the index of its first bucket, and the offset of each following span
+    /// is the gap since the end of the previous span.
+    pub fn new(offset: i32, length: u32) -> Self {
+        Self { offset, length }
+    }
+
+    /// Returns the offset of the span.
+    pub fn offset(&self) -> i32 {
+        self.offset
+    }
+
+    /// Returns the number of buckets in the span.
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+}
+
+/// A histogram in the form of a Prometheus native histogram.
+///
+/// Bucket `i` at schema `s` covers the values in `(base^(i - 1), base^i]`,
+/// where `base = 2^(2^-s)`, and values within the zero threshold are counted
+/// in the zero bucket. Buckets are stored as spans of consecutive indices,
+/// with each count encoded as the delta from the previous bucket.
+///
+/// When converting from a [`Histogram`] or [`SparseHistogram`], the schema is
+/// one more than the grouping power, up to the maximum schema of 8, and each
+/// bucket is re-bucketed by its midpoint. For grouping powers up to 7,
+/// converting back with [`to_histogram`](NativeHistogram::to_histogram) using
+/// the same config is exact. See [`ExponentialHistogram`] for details. The
+/// sum is exact if the histogram tracks a [`Summary`], and is otherwise
+/// estimated from the bucket midpoints.
+///
+/// The overflow count of a histogram recorded with
+/// [`RangePolicy::Count`](crate::RangePolicy) has no bucket to be converted
+/// into and is dropped, although an exact sum includes those values.
+///
+/// Only positive buckets are supported. Bucket counts above `i64::MAX` cannot
+/// be represented, and saturate at `i64::MAX` when converting from a
+/// histogram.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct NativeHistogram {
+    schema: i32,
+    zero_threshold: f64,
+    zero_count: u64,
+    count: u64,
+    sum: f64,
+    positive_spans: Vec<BucketSpan>,
+    positive_deltas: Vec<i64>,
+}
+
+impl NativeHistogram {
+    /// Creates a native histogram from its positive buckets.
+    ///
+    /// Returns an error if:
+    /// - the schema is outside of the range `-4..=8` (`IncompatibleParameters`)
+    /// - the span lengths do not match the number of deltas, or a bucket count
+    ///   is negative (`InvalidEncoding`)
+    pub fn new(
+        schema: i32,
+        zero_count: u64,
+        positive_spans: Vec<BucketSpan>,
+        positive_deltas: Vec<i64>,
+    ) -> Result<Self, Error> {
+        if !(MIN_SCHEMA..=MAX_SCHEMA).contains(&schema) {
+            return Err(Error::IncompatibleParameters);
+        }
+
+        // check that the spans cover the deltas and that the indices fit
+        let mut length: u64 = 0;
+        let mut next: i32 = 0;
+        for span in &positive_spans {
+            next = i32::try_from(span.length)
+                .ok()
+                .and_then(|l| next.checked_add(span.offset)?.checked_add(l))
+                .ok_or(Error::InvalidEncoding)?;
+            length += span.length as u64;
+        }
+        if length != positive_deltas.len() as u64 {
+            return Err(Error::InvalidEncoding);
+        }
+
+        let mut count = zero_count;
+        let mut current: i64 = 0;
+        for delta in &positive_deltas {
+            current = current.checked_add(*delta).ok_or(Error::InvalidEncoding)?;
+            if current < 0 {
+                return Err(Error::InvalidEncoding);
+            }
+            count = count.wrapping_add(current as u64);
+        }
+
+        Ok(Self {
+            schema,
+            zero_threshold: 0.0,
+            zero_count,
+            count,
+            sum: 0.0,
+            positive_spans,
+            positive_deltas,
+        })
+    }
+
+    /// Sets the zero threshold. Values with a magnitude up to the threshold
+    /// are counted in the zero bucket.
+    pub fn with_zero_threshold(mut self, zero_threshold: f64) -> Self {
+        self.zero_threshold = zero_threshold;
+        self
+    }
+
+    /// Sets the sum of the recorded values.
+    pub fn with_sum(mut self, sum: f64) -> Self {
+        self.sum = sum;
+        self
+    }
+
+    /// Creates a native histogram from the non-zero buckets of a histogram.
+    fn from_buckets<'a, T>(histogram: &'a T, config: &Config, summary: Option<Summary>) -> Self
+    where
+        &'a T: IntoIterator<Item = Bucket>,
+    {
+        let schema = exponential::scale(config).min(MAX_SCHEMA);
+        let buckets = histogram.into_iter().filter(|b| b.count() != 0);
+        let exponential = ExponentialHistogram::from_buckets(buckets, None, schema);
+
+        let mut positive_spans: Vec<BucketSpan> = Vec::new();
+        let mut positive_deltas = Vec::new();
+        let mut next = 0;
+        let mut previous: i64 = 0;
+        let mut count = exponential.zero_count();
+        for (index, bucket) in exponential.positive().iter() {
+            // the upper bound of bucket `i` is inclusive in both, but the
+            // indices differ by one
+            let index = index + 1;
+
+            match positive_spans.last_mut() {
+                Some(span) if index == next => span.length += 1,
+                Some(_) => positive_spans.push(BucketSpan::new(index - next, 1)),
+                None => positive_spans.push(BucketSpan::new(index, 1)),
+            }
+            // both counts are at most `i64::MAX`, so the delta cannot overflow
+            let current = bucket.min(i64::MAX as u64) as i64;
+            positive_deltas.push(current - previous);
+            count = count.wrapping_add(current as u64);
+
+            next = index + 1;
+            previous = current;
+        }
+
+        let sum = match summary {
+            Some(summary) => summary.sum() as f64,
+            None => histogram
+                .into_iter()
+                .filter(|b| b.count() != 0)
+                .map(|b| b.count() as f64 * Representative::Midpoint.value(&b))
+                .sum(),
+        };
+
+        Self {
+            schema,
+            zero_threshold: 0.0,
+            zero_count: exponential.zero_count(),
+            count,
+            sum,
+            positive_spans,
+            positive_deltas,
+        }
+    }
+
+    /// Returns the schema, which determines the resolution of the buckets.
+    pub fn schema(&self) -> i32 {
+        self.schema
+    }
+
+    /// Returns the zero threshold.
+    pub fn zero_threshold(&self) -> f64 {
+        self.zero_threshold
+    }
+
+    /// Returns the count of the zero bucket.
+    pub fn zero_count(&self) -> u64 {
+        self.zero_count
+    }
+
+    /// Returns the total count of all buckets.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the sum of the recorded values.
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    /// Returns the spans of the positive buckets.
+    pub fn positive_spans(&self) -> &[BucketSpan] {
+        &self.positive_spans
+    }
+
+    /// Returns the delta encoded counts of the positive buckets.
+    pub fn positive_deltas(&self) -> &[i64] {
+        &self.positive_deltas
+    }
+
+    /// Returns an iterator over the index and count of each positive bucket.
+    fn positive_buckets(&self) -> impl Iterator<Item = (i32, u64)> + '_ {
+        let indices = self.positive_spans.iter().scan(0, |next, span| {
+            let start = *next + span.offset;
+            *next = start + span.length as i32;
+            Some(start..*next)
+        });
+
+        indices
+            .flatten()
+            .zip(self.positive_deltas.iter().scan(0_u64, |count, delta| {
+                *count = count.wrapping_add(*delta as u64);
+                Some(*count)
+            }))
+    }
+
+    /// Converts to a sparse histogram with the provided config. Each bucket is
+    /// counted in the bucket containing its midpoint, rounded to the nearest
+    /// integer, and the zero bucket is counted as zero.
+    ///
+    /// Returns an error if:
+    /// - a midpoint is above the max value of the config (`OutOfRange`)
+    /// - the count of a bucket overflows (`Overflow`)
+    pub fn to_sparse(&self, config: &Config) -> Result<SparseHistogram, Error> {
+        let positive = self
+            .positive_buckets()
+            .filter(|(_, count)| *count != 0)
+            .map(|(index, count)| (index - 1, count));
+
+        exponential::to_sparse(config, self.schema, self.zero_count, positive)
+    }
+
+    /// Converts to a histogram with the provided config. See
+    /// [`to_sparse`](NativeHistogram::to_sparse) for details.
+    pub fn to_histogram(&self, config: &Config) -> Result<Histogram, Error> {
+        self.to_sparse(config).map(|h| Histogram::from(&h))
+    }
+}
+
+impl From<&Histogram> for NativeHistogram {
+    fn from(histogram: &Histogram) -> Self {
+        Self::from_buckets(histogram, &histogram.config, histogram.summary())
+    }
+}
+
+impl From<&SparseHistogram> for NativeHistogram {
+    fn from(histogram: &SparseHistogram) -> Self {
+        Self::from_buckets(histogram, &histogram.config, histogram.summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans() {
+        let mut histogram = Histogram::new(0, 8).unwrap().with_summary();
+        histogram.add(0, 3).unwrap();
+        histogram.add(1, 2).unwrap();
+        histogram.add(2, 5).unwrap();
+        histogram.add(100, 1).unwrap();
+
+        // schema 1 has buckets ..., (1/sqrt(2), 1], (1, sqrt(2)], (sqrt(2), 2],
+        // (2, 2*sqrt(2)], ... and the midpoint of 2..=3 is 2.5
+        let native = NativeHistogram::from(&histogram);
+        assert_eq!(native.schema(), 1);
+        assert_eq!(native.zero_count(), 3);
+        assert_eq!(native.count(), 11);
+        assert_eq!(native.sum(), 112.0);
+        assert_eq!(
+            native.positive_spans(),
+            &[
+                BucketSpan::new(0, 1),
+                BucketSpan::new(2, 1),
+                BucketSpan::new(10, 1)
+            ]
+        );
+        assert_eq!(native.positive_deltas(), &[2, 3, -4]);
+
+        let decoded = NativeHistogram::new(
+            native.schema(),
+            native.zero_count(),
+            native.positive_spans().to_vec(),
+            native.positive_deltas().to_vec(),
+        )
+        .unwrap()
+        .with_sum(native.sum());
+        assert_eq!(decoded, native);
+
+        let mut expected = histogram.clone();
+        expected.summary = None;
+        assert_eq!(native.to_histogram(&histogram.config()).unwrap(), expected);
+    }
+
+    #[test]
+    // Tests that converting back is exact for every supported grouping power
+    fn round_trip() {
+        for grouping_power in 0..=7 {
+            let mut histogram = Histogram::new(grouping_power, 40).unwrap();
+            for value in (0..1_000_000).step_by(1013) {
+                histogram.increment(value).unwrap();
+            }
+            histogram.add(1 << 39, 7).unwrap();
+
+            let sparse = SparseHistogram::from(&histogram);
+            let native = NativeHistogram::from(&sparse);
+            assert_eq!(native, NativeHistogram::from(&histogram));
+            assert_eq!(native.schema(), grouping_power as i32 + 1);
+            assert_eq!(native.to_sparse(&sparse.config()).unwrap(), sparse);
+        }
+
+        // the schema is limited to 8
+        let mut histogram = Histogram::new(10, 40).unwrap();
+        histogram.increment(1000).unwrap();
+        histogram.increment(1001).unwrap();
+        let native = NativeHistogram::from(&histogram);
+        assert_eq!(native.schema(), 8);
+        assert_eq!(native.positive_deltas(), &[2]);
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(
+            NativeHistogram::new(9, 0, Vec::new(), Vec::new()),
+            Err(Error::IncompatibleParameters)
+        );
+        assert_eq!(
+            NativeHistogram::new(0, 0, vec![BucketSpan::new(0, 2)], vec![1]),
+            Err(Error::InvalidEncoding)
+        );
+        assert_eq!(
+            NativeHistogram::new(0, 0, vec![BucketSpan::new(0, 2)], vec![1, -2]),
+            Err(Error::InvalidEncoding)
+        );
+        assert_eq!(
+            NativeHistogram::new(
+                0,
+                0,
+                vec![BucketSpan::new(i32::MAX, 1), BucketSpan::new(0, 1)],
+                vec![1, 0]
+            ),
+            Err(Error::InvalidEncoding)
+        );
+
+        // counts which cannot be represented saturate
+        let mut histogram = Histogram::new(0, 8).unwrap();
+        histogram.add(1, u64::MAX).unwrap();
+        histogram.add(2, 1).unwrap();
+        let native = NativeHistogram::from(&histogram);
+        assert_eq!(native.positive_deltas(), &[i64::MAX, 1 - i64::MAX]);
+        assert_eq!(native.count(), i64::MAX as u64 + 1);
+
+        let native = NativeHistogram::new(0, 0, vec![BucketSpan::new(70, 1)], vec![1]).unwrap();
+        assert_eq!(
+            native.to_sparse(&Config::new(7, 64).unwrap()),
+            Err(Error::OutOfRange)
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use crate::statistics::Representative;
+use crate::{Bucket, Error, Summary};
+use core::fmt::Write;
+
+/// Escapes a label value for the Prometheus text exposition format.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Renders the histogram as a Prometheus classic histogram in the text
+/// exposition format, without `# HELP` or `# TYPE` lines.
+///
+/// Each non-empty bucket is counted in the first boundary at or above its
+/// upper bound. Without boundaries, the upper bound of each non-empty bucket
+/// is used as a boundary. The overflow count is only included in `+Inf` and
+/// `_count`, and is left out of the sum when it is estimated without a
+/// summary.
+pub(crate) fn exposition<'a, T>(
+    histogram: &'a T,
+    summary: Option<Summary>,
+    overflow_count: u64,
+    name: &str,
+    labels: &[(&str, &str)],
+    boundaries: Option<&[u64]>,
+) -> Result<String, Error>
+where
+    &'a T: IntoIterator<Item = Bucket>,
+{
+    if let Some(boundaries) = boundaries {
+        if boundaries.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(Error::IncompatibleParameters);
+        }
+    }
+
+    let labels: String = labels
+        .iter()
+        .map(|(label, value)| format!("{label}=\"{}\",", escape(value)))
+        .collect();
+
+    let mut output = String::new();
+    let mut bucket_line = |le: &dyn core::fmt::Display, count: u128| {
+        // writing to a string cannot fail
+        writeln!(output, "{name}_bucket{{{labels}le=\"{le}\"}} {count}").unwrap();
+    };
+
+    let mut count: u128 = 0;
+    let mut sum = 0.0;
+    let mut remaining = boundaries.unwrap_or(&[]).iter().peekable();
+
+    for bucket in histogram.into_iter().filter(|b| b.count() != 0) {
+        match boundaries {
+            Some(_) => {
+                // emit the boundaries below this bucket with the counts so far
+                while let Some(le) = remaining.next_if(|le| **le < bucket.end()) {
+                    bucket_line(le, count);
+                }
+                count += bucket.count() as u128;
+            }
+            None => {
+                count += bucket.count() as u128;
+                bucket_line(&bucket.end(), count);
+            }
+        }
+        sum += bucket.count() as f64 * Representative::Midpoint.value(&bucket);
+    }
+
+    for le in remaining {
+        bucket_line(le, count);
+    }
+    count += overflow_count as u128;
+    bucket_line(&"+Inf", count);
+
+    let labels = labels.trim_end_matches(',');
+    let labels = if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{labels}}}")
+    };
+
+    match summary {
+        Some(summary) => writeln!(output, "{name}_sum{labels} {}", summary.sum()),
+        None => writeln!(output, "{name}_sum{labels} {sum}"),
+    }
+    .unwrap();
+    writeln!(output, "{name}_count{labels} {count}").unwrap();
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Histogram, SparseHistogram};
+
+    #[test]
+    fn bucket_bounds() {
+        let mut histogram = Histogram::new(2, 16).unwrap().with_summary();
+        histogram.add(3, 2).unwrap();
+        histogram.increment(9).unwrap();
+        histogram.increment(10).unwrap();
+
+        let expected = "\
+latency_bucket{method=\"GET\",le=\"3\"} 2
+latency_bucket{method=\"GET\",le=\"9\"} 3
+latency_bucket{method=\"GET\",le=\"11\"} 4
+latency_bucket{method=\"GET\",le=\"+Inf\"} 4
+latency_sum{method=\"GET\"} 25
+latency_count{method=\"GET\"} 4
+";
+        let output = histogram.to_prometheus("latency", &[("method", "GET")]);
+        assert_eq!(output, expected);
+
+        let sparse = SparseHistogram::from(&histogram);
+        assert_eq!(
+            sparse.to_prometheus("latency", &[("method", "GET")]),
+            expected
+        );
+    }
+
+    #[test]
+    fn boundaries() {
+        let mut histogram = Histogram::new(2, 16).unwrap();
+        histogram.add(3, 2).unwrap();
+        histogram.increment(9).unwrap();
+        histogram.increment(100).unwrap();
+
+        // the bucket for 9 is 8..=9, and is counted at 10
+        let output = histogram
+            .to_prometheus_with_boundaries("latency", &[], &[1, 5, 10, 20])
+            .unwrap();
+        assert_eq!(
+            output,
+            "\
+latency_bucket{le=\"1\"} 0
+latency_bucket{le=\"5\"} 2
+latency_bucket{le=\"10\"} 3
+latency_bucket{le=\"20\"} 3
+latency_bucket{le=\"+Inf\"} 4
+latency_sum 118
+latency_count 4
+"
+        );
+
+        assert_eq!(
+            histogram.to_prometheus_with_boundaries("latency", &[], &[5, 5]),
+            Err(crate::Error::IncompatibleParameters)
+        );
+    }
+
+    #[test]
+    fn empty() {
+        let histogram = Histogram::new(7, 64).unwrap();
+        assert_eq!(
+            histogram.to_prometheus("x", &[("path", "a\"b\\c\nd")]),
+            "\
+x_bucket{path=\"a\\\"b\\\\c\\nd\",le=\"+Inf\"} 0
+x_sum{path=\"a\\\"b\\\\c\\nd\"} 0
+x_count{path=\"a\\\"b\\\\c\\nd\"} 0
+"
+        );
+    }
+}
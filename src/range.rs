@@ -0,0 +1,68 @@
+//! Handling of values above the max value of a histogram's config.
+
+use crate::{Config, Error};
+
+/// Selects what happens when a value above the max value of the config is
+/// recorded into a [`Histogram`](crate::Histogram),
+/// [`AtomicHistogram`](crate::AtomicHistogram), or
+/// [`SparseHistogram`](crate::SparseHistogram).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum RangePolicy {
+    /// Return `Err(Error::OutOfRange)` and discard the value.
+    #[default]
+    Error,
+    /// Count the value in the last bucket.
+    Clamp,
+    /// Count the value in a dedicated overflow counter, which is kept apart
+    /// from the buckets and is not included in percentiles or other queries
+    /// on the buckets.
+    Count,
+}
+
+impl RangePolicy {
+    /// Returns the index of the bucket to count the value in, or `None` if it
+    /// is counted in the overflow counter.
+    pub(crate) fn index(self, config: &Config, value: u64) -> Result<Option<usize>, Error> {
+        match config.value_to_index(value) {
+            Ok(index) => Ok(Some(index)),
+            Err(e) => match self {
+                RangePolicy::Error => Err(e),
+                RangePolicy::Clamp => Ok(Some(config.total_buckets() - 1)),
+                RangePolicy::Count => Ok(None),
+            },
+        }
+    }
+
+    pub(crate) fn is_default(&self) -> bool {
+        *self == RangePolicy::Error
+    }
+}
+
+#[cfg(feature = "serde")]
+pub(crate) fn is_zero(count: &u64) -> bool {
+    *count == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index() {
+        let config = Config::new(4, 10).unwrap();
+        let last = config.total_buckets() - 1;
+
+        for policy in [RangePolicy::Error, RangePolicy::Clamp, RangePolicy::Count] {
+            assert_eq!(policy.index(&config, 1023), Ok(Some(last)));
+        }
+        assert_eq!(
+            RangePolicy::Error.index(&config, 1024),
+            Err(Error::OutOfRange)
+        );
+        assert_eq!(RangePolicy::Clamp.index(&config, u64::MAX), Ok(Some(last)));
+        assert_eq!(RangePolicy::Count.index(&config, 1024), Ok(None));
+    }
+}
@@ -0,0 +1,192 @@
+use crate::{Bucket, Config, Error};
+
+/// The fraction of observations which are less than or equal to some value.
+///
+/// Since only the bucket of each observation is known, observations which
+/// fall into the same bucket as the value may or may not be less than or
+/// equal to it. The lower and upper bounds reflect this uncertainty and are
+/// equal when the value is the upper bound of its bucket.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rank {
+    lower: f64,
+    upper: f64,
+    estimate: f64,
+}
+
+impl Rank {
+    /// Computes the rank of a value within the bucket `start..=end` from the
+    /// number of observations `below` the bucket, `within` the bucket, and in
+    /// `total`.
+    pub(crate) fn new(
+        value: u64,
+        start: u64,
+        end: u64,
+        below: u128,
+        within: u128,
+        total: u128,
+    ) -> Self {
+        // the fraction of the values within the bucket which are less than
+        // or equal to the value
+        let fraction = (value - start + 1) as f64 / ((end - start) as f64 + 1.0);
+
+        let upper = (below + within) as f64 / total as f64;
+        let lower = if value == end {
+            upper
+        } else {
+            below as f64 / total as f64
+        };
+        let estimate = (below as f64 + within as f64 * fraction) / total as f64;
+
+        Self {
+            lower,
+            upper,
+            estimate,
+        }
+    }
+
+    /// Returns the fraction of observations which are known to be less than
+    /// or equal to the value.
+    pub fn lower(&self) -> f64 {
+        self.lower
+    }
+
+    /// Returns the fraction of observations which may be less than or equal
+    /// to the value.
+    pub fn upper(&self) -> f64 {
+        self.upper
+    }
+
+    /// Returns an estimate of the fraction of observations which are less
+    /// than or equal to the value, assuming observations are uniformly
+    /// distributed across the values within the value's bucket.
+    pub fn estimate(&self) -> f64 {
+        self.estimate
+    }
+}
+
+/// Computes the rank of each value from the buckets of a histogram. The
+/// results are sorted by value. Returns `None` if the histogram is empty.
+pub(crate) fn ranks<'a, T>(
+    histogram: &'a T,
+    config: &Config,
+    values: &[u64],
+) -> Result<Option<Vec<(u64, Rank)>>, Error>
+where
+    &'a T: IntoIterator<Item = Bucket>,
+{
+    // validate all the values
+    for value in values {
+        config.value_to_index(*value)?;
+    }
+
+    // get the total count
+    let total_count: u128 = histogram
+        .into_iter()
+        .map(|bucket| bucket.count() as u128)
+        .sum();
+
+    // empty histogram, no ranks available
+    if total_count == 0 {
+        return Ok(None);
+    }
+
+    // sort the requested values so we can find them in a single pass
+    let mut values = values.to_vec();
+    values.sort_unstable();
+
+    let mut buckets = histogram
+        .into_iter()
+        .filter(|bucket| bucket.count() != 0)
+        .peekable();
+    let mut below: u128 = 0;
+
+    let result = values
+        .iter()
+        .map(|value| {
+            let index = config.value_to_index(*value).unwrap();
+            let start = config.index_to_lower_bound(index);
+            let end = config.index_to_upper_bound(index);
+
+            // accumulate all the buckets which are entirely below the value
+            while let Some(bucket) = buckets.next_if(|bucket| bucket.end() < start) {
+                below += bucket.count() as u128;
+            }
+
+            // the bucket containing the value, if it is non-empty
+            let within = buckets
+                .peek()
+                .filter(|bucket| bucket.start() == start)
+                .map(|bucket| bucket.count() as u128)
+                .unwrap_or(0);
+
+            (
+                *value,
+                Rank::new(*value, start, end, below, within, total_count),
+            )
+        })
+        .collect();
+
+    Ok(Some(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, Histogram, SparseHistogram};
+
+    #[test]
+    fn rank() {
+        let mut histogram = Histogram::new(2, 16).unwrap();
+        assert_eq!(histogram.rank(10), Ok(None));
+
+        for value in 1..=100 {
+            let _ = histogram.increment(value);
+        }
+
+        // values in the linear region are exact
+        let rank = histogram.rank(4).unwrap().unwrap();
+        assert_eq!(rank.lower(), 0.04);
+        assert_eq!(rank.upper(), 0.04);
+        assert_eq!(rank.estimate(), 0.04);
+
+        // 64..=79 is a single bucket
+        let rank = histogram.rank(64).unwrap().unwrap();
+        assert_eq!(rank.lower(), 0.63);
+        assert_eq!(rank.upper(), 0.79);
+        assert_eq!(rank.estimate(), 0.64);
+
+        let rank = histogram.rank(79).unwrap().unwrap();
+        assert_eq!(rank.lower(), 0.79);
+        assert_eq!(rank.upper(), 0.79);
+        assert_eq!(rank.estimate(), 0.79);
+
+        let rank = histogram.rank(1000).unwrap().unwrap();
+        assert_eq!(rank.lower(), 1.0);
+        assert_eq!(rank.upper(), 1.0);
+
+        let rank = histogram.rank(0).unwrap().unwrap();
+        assert_eq!(rank.upper(), 0.0);
+
+        assert_eq!(histogram.rank(65_536), Err(Error::OutOfRange));
+    }
+
+    #[test]
+    fn ranks() {
+        let mut histogram = Histogram::new(7, 64).unwrap();
+        for value in 1..=1000 {
+            let _ = histogram.increment(value);
+        }
+
+        let ranks = histogram.ranks(&[900, 100, 500]).unwrap().unwrap();
+        let values: Vec<u64> = ranks.iter().map(|(v, _)| *v).collect();
+        assert_eq!(values, vec![100, 500, 900]);
+
+        for (value, rank) in &ranks {
+            let exact = *value as f64 / 1000.0;
+            assert!(rank.lower() <= exact && exact <= rank.upper());
+            assert_eq!(histogram.rank(*value).unwrap().unwrap(), *rank);
+        }
+
+        let sparse = SparseHistogram::from(&histogram);
+        assert_eq!(sparse.ranks(&[900, 100, 500]).unwrap().unwrap(), ranks);
+    }
+}
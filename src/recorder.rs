@@ -0,0 +1,372 @@
+use crate::summary::Summary;
+use crate::{AtomicHistogram, Error, Histogram};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Records values into per-thread buffers which are periodically flushed into
+/// a shared [`AtomicHistogram`].
+///
+/// Each thread obtains a [`LocalRecorder`] with
+/// [`local`](BufferedRecorder::local) and records into it without touching
+/// the shared histogram. A local recorder flushes its buffer into the shared histogram once
+/// `flush_threshold` observations have accumulated, when
+/// [`LocalRecorder::flush`] is called, and when it is dropped, for example on
+/// thread exit when it is stored in a `thread_local!`.
+///
+/// Buffered observations are not visible in the shared histogram until they
+/// are flushed. Use [`flush`](BufferedRecorder::flush),
+/// [`load`](BufferedRecorder::load), or [`drain`](BufferedRecorder::drain)
+/// to flush every registered thread before taking a snapshot. The buffer of
+/// each thread is behind its own lock, which is only contended while another
+/// thread flushes it.
+///
+/// ```
+/// use histogram::{AtomicHistogram, BufferedRecorder, LocalRecorder};
+/// use std::sync::{Arc, LazyLock};
+///
+/// static RECORDER: LazyLock<BufferedRecorder> = LazyLock::new(|| {
+///     BufferedRecorder::new(Arc::new(AtomicHistogram::new(7, 64).unwrap()), 1024)
+/// });
+///
+/// thread_local! {
+///     static LOCAL: LocalRecorder = RECORDER.local();
+/// }
+///
+/// LOCAL.with(|local| local.increment(42)).unwrap();
+///
+/// let snapshot = RECORDER.load().unwrap();
+/// assert_eq!(snapshot.percentile(1.0).unwrap().unwrap().end(), 42);
+/// ```
+#[derive(Clone)]
+pub struct BufferedRecorder {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    histogram: Arc<AtomicHistogram>,
+    flush_threshold: u64,
+    buffers: Mutex<Vec<Arc<Mutex<Buffer>>>>,
+}
+
+/// The buffered observations of a single thread.
+struct Buffer {
+    histogram: Histogram,
+    /// Indices of the buckets which are non-zero, so that flushing does not
+    /// need to scan every bucket.
+    touched: Vec<usize>,
+    pending: u64,
+}
+
+impl Buffer {
+    /// Adds the buffered observations to the histogram and clears the buffer,
+    /// even if a counter of the histogram overflows. See
+    /// [`AtomicHistogram::add_buckets`] for `wrap_on_overflow`.
+    fn flush(&mut self, histogram: &AtomicHistogram, wrap_on_overflow: bool) -> Result<(), Error> {
+        if self.pending == 0 {
+            return Ok(());
+        }
+
+        let result = histogram.add_buckets(&self.histogram, &self.touched, wrap_on_overflow);
+
+        for index in self.touched.drain(..) {
+            self.histogram.buckets[index] = 0;
+        }
+        self.histogram.overflow_count = 0;
+        if let Some(summary) = &mut self.histogram.summary {
+            **summary = Summary::new();
+        }
+        self.pending = 0;
+
+        result
+    }
+}
+
+/// Locks a mutex, recovering the guard if another thread panicked while
+/// holding it. The buffers are never left in an inconsistent state.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl BufferedRecorder {
+    /// Creates a new recorder which flushes into `histogram`. Local recorders
+    /// flush automatically once `flush_threshold` observations have been
+    /// buffered.
+    pub fn new(histogram: Arc<AtomicHistogram>, flush_threshold: u64) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                histogram,
+                flush_threshold,
+                buffers: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Returns the shared histogram.
+    pub fn histogram(&self) -> &Arc<AtomicHistogram> {
+        &self.shared.histogram
+    }
+
+    /// Creates and registers a new local recorder, which is intended to be
+    /// used by a single thread.
+    pub fn local(&self) -> LocalRecorder {
+        let histogram = Histogram::with_config(&self.shared.histogram.config())
+            .with_range_policy(self.shared.histogram.range_policy())
+            .with_counter_policy(self.shared.histogram.counter_policy());
+        let histogram = if self.shared.histogram.has_summary() {
+            histogram.with_summary()
+        } else {
+            histogram
+        };
+
+        let buffer = Arc::new(Mutex::new(Buffer {
+            histogram,
+            touched: Vec::new(),
+            pending: 0,
+        }));
+        lock(&self.shared.buffers).push(buffer.clone());
+
+        LocalRecorder {
+            shared: self.shared.clone(),
+            buffer,
+        }
+    }
+
+    /// Flushes the buffers of all registered local recorders into the shared
+    /// histogram.
+    ///
+    /// Every buffer is flushed even if one of them overflows a counter. The
+    /// first error is returned, as described in [`LocalRecorder::add`].
+    pub fn flush(&self) -> Result<(), Error> {
+        let mut result = Ok(());
+        for buffer in lock(&self.shared.buffers).iter() {
+            result = result.and(lock(buffer).flush(&self.shared.histogram, false));
+        }
+        result
+    }
+
+    /// Flushes all registered local recorders and reads the shared histogram
+    /// into a new `Histogram`.
+    ///
+    /// Returns the error of [`flush`](BufferedRecorder::flush), if any,
+    /// instead of the snapshot.
+    pub fn load(&self) -> Result<Histogram, Error> {
+        self.flush()?;
+        Ok(self.shared.histogram.load())
+    }
+
+    // NOTE: once stabilized, `target_has_atomic_load_store` is more correct. https://github.com/rust-lang/rust/issues/94039
+    #[cfg(target_has_atomic = "64")]
+    /// Flushes all registered local recorders and drains the shared histogram
+    /// into a new `Histogram`. See [`AtomicHistogram::drain`].
+    ///
+    /// Returns the error of [`flush`](BufferedRecorder::flush), if any, in
+    /// which case the shared histogram is not drained.
+    pub fn drain(&self) -> Result<Histogram, Error> {
+        self.flush()?;
+        Ok(self.shared.histogram.drain())
+    }
+}
+
+impl std::fmt::Debug for BufferedRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BufferedRecorder")
+            .field("histogram", &self.shared.histogram)
+            .field("flush_threshold", &self.shared.flush_threshold)
+            .finish()
+    }
+}
+
+/// A per-thread buffer created by [`BufferedRecorder::local`]. The buffer is
+/// flushed into the shared histogram when the local recorder is dropped.
+pub struct LocalRecorder {
+    shared: Arc<Shared>,
+    buffer: Arc<Mutex<Buffer>>,
+}
+
+impl LocalRecorder {
+    /// Increment the buffered counter for the bucket corresponding to the
+    /// provided value by one.
+    pub fn increment(&self, value: u64) -> Result<(), Error> {
+        self.add(value, 1)
+    }
+
+    /// Add some count to the buffered counter for the bucket corresponding to
+    /// the provided value, flushing the buffer if the threshold is reached.
+    ///
+    /// The buffered counters and the shared histogram are updated according
+    /// to the [`CounterPolicy`](crate::CounterPolicy) of the shared histogram.
+    /// If it is checked, an error is returned when flushing overflows a
+    /// counter of the shared histogram, and those counts are discarded.
+    pub fn add(&self, value: u64, count: u64) -> Result<(), Error> {
+        let mut buffer = lock(&self.buffer);
+        let buffer = &mut *buffer;
+
+        let histogram = &mut buffer.histogram;
+        let index = histogram.range_policy.index(&histogram.config, value)?;
+        let empty = index.is_some_and(|index| histogram.buckets[index] == 0);
+        histogram.add(value, count)?;
+        // a bucket is touched once, when it becomes non-zero. It cannot wrap
+        // back to zero before the buffer is flushed, since the pending count
+        // reaches the threshold first
+        if let Some(index) = index.filter(|index| empty && histogram.buckets[*index] != 0) {
+            buffer.touched.push(index);
+        }
+        buffer.pending = buffer.pending.saturating_add(count);
+
+        if buffer.pending >= self.shared.flush_threshold {
+            buffer.flush(&self.shared.histogram, false)?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the buffered observations into the shared histogram. See
+    /// [`add`](LocalRecorder::add) for the errors.
+    pub fn flush(&self) -> Result<(), Error> {
+        lock(&self.buffer).flush(&self.shared.histogram, false)
+    }
+}
+
+impl Drop for LocalRecorder {
+    fn drop(&mut self) {
+        // there is nowhere to report an overflow, so counters which would
+        // overflow wrap rather than losing the buffered counts
+        let _ = lock(&self.buffer).flush(&self.shared.histogram, true);
+        lock(&self.shared.buffers).retain(|buffer| !Arc::ptr_eq(buffer, &self.buffer));
+    }
+}
+
+impl std::fmt::Debug for LocalRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LocalRecorder")
+            .field("pending", &lock(&self.buffer).pending)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CounterPolicy;
+
+    #[test]
+    // Tests the automatic and explicit flushing of a local recorder
+    fn flush() {
+        let histogram = Arc::new(AtomicHistogram::new(7, 64).unwrap());
+        let recorder = BufferedRecorder::new(histogram.clone(), 3);
+        let local = recorder.local();
+
+        local.increment(1).unwrap();
+        local.increment(1).unwrap();
+        assert_eq!(histogram.load().as_slice()[1], 0);
+
+        local.increment(2).unwrap();
+        assert_eq!(histogram.load().as_slice()[1], 2);
+        assert_eq!(histogram.load().as_slice()[2], 1);
+
+        local.increment(3).unwrap();
+        local.flush().unwrap();
+        assert_eq!(histogram.load().as_slice()[3], 1);
+
+        local.increment(4).unwrap();
+        assert_eq!(recorder.load().unwrap().as_slice()[4], 1);
+
+        local.increment(5).unwrap();
+        drop(local);
+        assert_eq!(histogram.load().as_slice()[5], 1);
+        assert!(lock(&recorder.shared.buffers).is_empty());
+
+        let recorder = BufferedRecorder::new(Arc::new(AtomicHistogram::new(7, 32).unwrap()), 3);
+        assert_eq!(recorder.local().increment(u64::MAX), Err(Error::OutOfRange));
+    }
+
+    #[test]
+    // Tests that a bucket is flushed once after a zero count
+    fn touched() {
+        let histogram = Arc::new(AtomicHistogram::new(7, 64).unwrap());
+        let recorder = BufferedRecorder::new(histogram.clone(), u64::MAX);
+        let local = recorder.local();
+
+        local.add(5, 0).unwrap();
+        local.add(5, 3).unwrap();
+        local.flush().unwrap();
+        assert_eq!(histogram.load().as_slice()[5], 3);
+    }
+
+    #[test]
+    // Tests that flushing follows the counter policy of the shared histogram
+    fn counter_policy() {
+        let histogram = Arc::new(
+            AtomicHistogram::new(7, 64)
+                .unwrap()
+                .with_counter_policy(CounterPolicy::Saturating),
+        );
+        histogram.add(5, u64::MAX - 1).unwrap();
+        let recorder = BufferedRecorder::new(histogram.clone(), u64::MAX);
+        let local = recorder.local();
+        local.add(5, 3).unwrap();
+        local.flush().unwrap();
+        assert_eq!(histogram.load().as_slice()[5], u64::MAX);
+
+        let histogram = Arc::new(
+            AtomicHistogram::new(7, 64)
+                .unwrap()
+                .with_counter_policy(CounterPolicy::Checked),
+        );
+        histogram.add(5, u64::MAX - 1).unwrap();
+        let recorder = BufferedRecorder::new(histogram.clone(), 3);
+        let local = recorder.local();
+        local.increment(6).unwrap();
+        assert_eq!(local.add(5, 2), Err(Error::Overflow));
+        let snapshot = histogram.load();
+        assert_eq!(snapshot.as_slice()[5], u64::MAX - 1);
+        assert_eq!(snapshot.as_slice()[6], 1);
+
+        // a snapshot is not taken if flushing overflows
+        let recorder = BufferedRecorder::new(histogram.clone(), u64::MAX);
+        let local = recorder.local();
+        local.add(5, 2).unwrap();
+        assert_eq!(recorder.drain(), Err(Error::Overflow));
+        assert_eq!(histogram.load().as_slice()[5], u64::MAX - 1);
+
+        // dropping a local recorder wraps rather than losing its counts
+        local.add(5, 3).unwrap();
+        drop(local);
+        assert_eq!(histogram.load().as_slice()[5], 1);
+    }
+
+    #[test]
+    // Tests that thread exit and forced flushes reach the shared histogram
+    fn threads() {
+        let histogram = Arc::new(AtomicHistogram::new(7, 64).unwrap().with_summary());
+        let recorder = BufferedRecorder::new(histogram, 1_000_000);
+
+        let exited: Vec<_> = (0..4)
+            .map(|_| {
+                let recorder = recorder.clone();
+                std::thread::spawn(move || {
+                    let local = recorder.local();
+                    for value in 1..=100 {
+                        local.increment(value).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in exited {
+            thread.join().unwrap();
+        }
+
+        let local = recorder.local();
+        local.add(1000, 10).unwrap();
+        local.increment(1000).unwrap();
+        let snapshot = recorder.load().unwrap();
+        assert_eq!(snapshot.as_slice().iter().sum::<u64>(), 411);
+        let summary = snapshot.summary().unwrap();
+        assert_eq!(summary.min(), Some(1));
+        assert_eq!(summary.max(), Some(1000));
+        assert_eq!(summary.count(), 411);
+        assert_eq!(summary.sum(), 4 * 5050 + 11_000);
+
+        // flushing again does not double count
+        assert_eq!(recorder.load(), Ok(snapshot));
+    }
+}
@@ -0,0 +1,250 @@
+//! Changing the max value power of a histogram. The bucket index of a value
+//! does not depend on the max value power, so the buckets keep their indices
+//! and only the buckets above the new max value are affected.
+
+use crate::{Config, Error, Histogram, SparseHistogram};
+
+/// Selects what happens to the counts above the new max value when a
+/// histogram is truncated to a smaller max value power.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TruncationPolicy {
+    /// Return `Err(Error::OutOfRange)` if any bucket above the new max value
+    /// has a non-zero count.
+    #[default]
+    Error,
+    /// Add the counts above the new max value to the last bucket.
+    Clamp,
+    /// Discard the counts above the new max value.
+    Drop,
+}
+
+/// Returns the config for a larger max value power.
+fn expanded(config: &Config, max_value_power: u8) -> Result<Config, Error> {
+    if max_value_power <= config.max_value_power() {
+        return Err(Error::IncompatibleParameters);
+    }
+
+    Config::new(config.grouping_power(), max_value_power)
+}
+
+/// Returns the config for a smaller max value power.
+fn truncated(config: &Config, max_value_power: u8) -> Result<Config, Error> {
+    if max_value_power >= config.max_value_power() {
+        return Err(Error::IncompatibleParameters);
+    }
+
+    Config::new(config.grouping_power(), max_value_power)
+}
+
+/// Applies the policy to the total count above the new max value, returning
+/// the count to add to the last bucket.
+fn excess(policy: TruncationPolicy, count: u128) -> Result<u64, Error> {
+    match policy {
+        _ if count == 0 => Ok(0),
+        TruncationPolicy::Error => Err(Error::OutOfRange),
+        TruncationPolicy::Clamp => u64::try_from(count).map_err(|_| Error::Overflow),
+        TruncationPolicy::Drop => Ok(0),
+    }
+}
+
+impl Histogram {
+    /// Returns a copy of the histogram with a larger max value power. This is
+    /// lossless, since the bucket of each value does not depend on the max
+    /// value power.
+    ///
+    /// Returns an error if the max value power is not greater than the current
+    /// max value power or is invalid.
+    pub fn expand(&self, max_value_power: u8) -> Result<Histogram, Error> {
+        let config = expanded(&self.config, max_value_power)?;
+
+        let mut histogram = Histogram::with_config(&config);
+        histogram.buckets[..self.buckets.len()].copy_from_slice(&self.buckets);
+        histogram.summary = self.summary.clone();
+        histogram.range_policy = self.range_policy;
+        histogram.overflow_count = self.overflow_count;
+        histogram.counter_policy = self.counter_policy;
+
+        Ok(histogram)
+    }
+
+    /// Returns a copy of the histogram with a smaller max value power, along
+    /// with the total count above the new max value, which is handled
+    /// according to the `policy`.
+    ///
+    /// Returns an error if the max value power is not less than the current
+    /// max value power or is invalid, if the policy is
+    /// [`TruncationPolicy::Error`] and there are counts above the new max
+    /// value, or if clamping overflows the last bucket.
+    ///
+    /// The summary is kept unless counts are dropped. The returned count
+    /// saturates at `u64::MAX`.
+    pub fn truncate(
+        &self,
+        max_value_power: u8,
+        policy: TruncationPolicy,
+    ) -> Result<(Histogram, u64), Error> {
+        let config = truncated(&self.config, max_value_power)?;
+        let (kept, above) = self.buckets.split_at(config.total_buckets());
+        let above: u128 = above.iter().map(|count| *count as u128).sum();
+
+        let mut histogram = Histogram::with_config(&config);
+        histogram.buckets.copy_from_slice(kept);
+        let last = histogram.buckets.last_mut().unwrap();
+        *last = last
+            .checked_add(excess(policy, above)?)
+            .ok_or(Error::Overflow)?;
+        if above == 0 || policy != TruncationPolicy::Drop {
+            histogram.summary = self.summary.clone();
+        }
+        histogram.range_policy = self.range_policy;
+        histogram.overflow_count = self.overflow_count;
+        histogram.counter_policy = self.counter_policy;
+
+        // the count can only exceed a u64 when it is dropped
+        Ok((histogram, above.min(u64::MAX as u128) as u64))
+    }
+}
+
+impl SparseHistogram {
+    /// Returns a copy of the histogram with a larger max value power. See
+    /// [`Histogram::expand`].
+    pub fn expand(&self, max_value_power: u8) -> Result<SparseHistogram, Error> {
+        let config = expanded(&self.config, max_value_power)?;
+
+        Ok(SparseHistogram {
+            config,
+            index: self.index.clone(),
+            count: self.count.clone(),
+            summary: self.summary,
+            range_policy: self.range_policy,
+            overflow_count: self.overflow_count,
+            counter_policy: self.counter_policy,
+        })
+    }
+
+    /// Returns a copy of the histogram with a smaller max value power, along
+    /// with the total count above the new max value. See
+    /// [`Histogram::truncate`].
+    pub fn truncate(
+        &self,
+        max_value_power: u8,
+        policy: TruncationPolicy,
+    ) -> Result<(SparseHistogram, u64), Error> {
+        let config = truncated(&self.config, max_value_power)?;
+        let last = config.total_buckets() as u32 - 1;
+        let kept = self.index.partition_point(|index| *index <= last);
+        let above: u128 = self.count[kept..].iter().map(|c| *c as u128).sum();
+
+        let mut index = self.index[..kept].to_vec();
+        let mut count = self.count[..kept].to_vec();
+        let clamped = excess(policy, above)?;
+        if clamped != 0 {
+            if index.last() == Some(&last) {
+                let last = count.last_mut().unwrap();
+                *last = last.checked_add(clamped).ok_or(Error::Overflow)?;
+            } else {
+                index.push(last);
+                count.push(clamped);
+            }
+        }
+
+        let summary = if above == 0 || policy != TruncationPolicy::Drop {
+            self.summary
+        } else {
+            None
+        };
+
+        let histogram = SparseHistogram {
+            config,
+            index,
+            count,
+            summary,
+            range_policy: self.range_policy,
+            overflow_count: self.overflow_count,
+            counter_policy: self.counter_policy,
+        };
+
+        Ok((histogram, above.min(u64::MAX as u128) as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand() {
+        let mut histogram = Histogram::new(4, 10).unwrap().with_summary();
+        for value in [0, 17, 500, 1023] {
+            histogram.increment(value).unwrap();
+        }
+
+        let expanded = histogram.expand(16).unwrap();
+        assert_eq!(expanded.config(), Config::new(4, 16).unwrap());
+        assert_eq!(expanded.summary(), histogram.summary());
+        assert_eq!(
+            expanded.percentiles(&[0.0, 0.5, 1.0]),
+            histogram.percentiles(&[0.0, 0.5, 1.0])
+        );
+        assert_eq!(histogram.expand(10), Err(Error::IncompatibleParameters));
+        assert_eq!(histogram.expand(65), Err(Error::MaxPowerTooHigh));
+
+        let sparse = SparseHistogram::from(&histogram).expand(16).unwrap();
+        assert_eq!(sparse, SparseHistogram::from(&expanded));
+
+        // truncating back is lossless
+        assert_eq!(
+            expanded.truncate(10, TruncationPolicy::Error),
+            Ok((histogram, 0))
+        );
+    }
+
+    #[test]
+    fn truncate() {
+        let mut histogram = Histogram::new(4, 16).unwrap().with_summary();
+        histogram.add(100, 2).unwrap();
+        histogram.add(1023, 1).unwrap();
+        histogram.add(5000, 3).unwrap();
+        histogram.add(60_000, 4).unwrap();
+        let sparse = SparseHistogram::from(&histogram);
+
+        assert_eq!(
+            histogram.truncate(10, TruncationPolicy::Error),
+            Err(Error::OutOfRange)
+        );
+        assert_eq!(
+            sparse.truncate(10, TruncationPolicy::Error),
+            Err(Error::OutOfRange)
+        );
+
+        let (clamped, count) = histogram.truncate(10, TruncationPolicy::Clamp).unwrap();
+        assert_eq!(count, 7);
+        assert_eq!(clamped.as_slice().last(), Some(&8));
+        assert_eq!(clamped.summary(), histogram.summary());
+        let (sparse_clamped, count) = sparse.truncate(10, TruncationPolicy::Clamp).unwrap();
+        assert_eq!(count, 7);
+        assert_eq!(sparse_clamped, SparseHistogram::from(&clamped));
+
+        let (dropped, count) = histogram.truncate(10, TruncationPolicy::Drop).unwrap();
+        assert_eq!(count, 7);
+        assert_eq!(dropped.as_slice().iter().sum::<u64>(), 3);
+        assert_eq!(dropped.summary(), None);
+        let (sparse_dropped, count) = sparse.truncate(10, TruncationPolicy::Drop).unwrap();
+        assert_eq!(count, 7);
+        assert_eq!(sparse_dropped, SparseHistogram::from(&dropped));
+
+        // the last bucket is added when it was empty
+        let (sparse_clamped, _) = sparse.truncate(12, TruncationPolicy::Clamp).unwrap();
+        assert_eq!(sparse_clamped.index().last(), Some(&143));
+        assert_eq!(sparse_clamped.count(), &[2, 1, 7]);
+
+        assert_eq!(
+            histogram.truncate(16, TruncationPolicy::Clamp),
+            Err(Error::IncompatibleParameters)
+        );
+        assert_eq!(
+            histogram.truncate(4, TruncationPolicy::Clamp),
+            Err(Error::MaxPowerTooLow)
+        );
+    }
+}
@@ -0,0 +1,305 @@
+use crate::summary;
+use crate::{AtomicHistogram, Config, CounterPolicy, Error, Histogram, RangePolicy};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Used to assign each thread a shard in a round-robin fashion.
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+std::thread_local! {
+    static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Aligns the contained value to its own cache lines. 128 bytes covers
+/// adjacent-line prefetching on modern x86 and the line size on Apple
+/// silicon.
+///
+/// Only the [`AtomicHistogram`] itself is padded, which keeps the overflow
+/// counters of the shards apart. The buckets of each shard are a separate
+/// allocation, so the same bucket in different shards is at least the size
+/// of the bucket array apart, but the first and last buckets of one shard may
+/// share a line with the allocation next to it.
+#[repr(align(128))]
+struct CachePadded<T>(T);
+
+/// An atomic histogram which is striped across several independent shards to
+/// reduce contention between threads recording the same values.
+///
+/// With a single [`AtomicHistogram`], every thread recording into a popular
+/// bucket contends on the same cache line. Here each thread records into one
+/// of the shards, chosen by the thread or by a caller-provided hint, and the
+/// shards are summed when a snapshot is taken.
+pub struct ShardedAtomicHistogram {
+    config: Config,
+    shards: Box<[CachePadded<AtomicHistogram>]>,
+}
+
+impl ShardedAtomicHistogram {
+    /// Construct a new sharded atomic histogram from the provided parameters
+    /// and number of shards. See the documentation for [`crate::Config`] to
+    /// understand their meaning.
+    ///
+    /// Returns `Err(Error::IncompatibleParameters)` if `shards` is zero.
+    pub fn new(grouping_power: u8, max_value_power: u8, shards: usize) -> Result<Self, Error> {
+        let config = Config::new(grouping_power, max_value_power)?;
+
+        Self::with_config(&config, shards)
+    }
+
+    /// Creates a new sharded atomic histogram with the provided number of
+    /// shards using a provided [`crate::Config`].
+    ///
+    /// Returns `Err(Error::IncompatibleParameters)` if `shards` is zero.
+    pub fn with_config(config: &Config, shards: usize) -> Result<Self, Error> {
+        if shards == 0 {
+            return Err(Error::IncompatibleParameters);
+        }
+
+        let shards = (0..shards)
+            .map(|_| CachePadded(AtomicHistogram::with_config(config)))
+            .collect();
+
+        Ok(Self {
+            config: *config,
+            shards,
+        })
+    }
+
+    /// Enables tracking of the exact minimum, maximum, sum, and count of the
+    /// recorded values. See [`AtomicHistogram::with_summary`].
+    pub fn with_summary(self) -> Self {
+        let shards = self
+            .shards
+            .into_vec()
+            .into_iter()
+            .map(|shard| CachePadded(shard.0.with_summary()))
+            .collect();
+
+        Self {
+            config: self.config,
+            shards,
+        }
+    }
+
+    /// Sets how values above the max value of the config are recorded. See
+    /// [`AtomicHistogram::with_range_policy`].
+    pub fn with_range_policy(self, policy: RangePolicy) -> Self {
+        let shards = self
+            .shards
+            .into_vec()
+            .into_iter()
+            .map(|shard| CachePadded(shard.0.with_range_policy(policy)))
+            .collect();
+
+        Self {
+            config: self.config,
+            shards,
+        }
+    }
+
+    /// Sets how a counter which would overflow is updated when a value is
+    /// recorded. See [`AtomicHistogram::with_counter_policy`]. Each shard
+    /// applies the policy to its own counters.
+    pub fn with_counter_policy(self, policy: CounterPolicy) -> Self {
+        let shards = self
+            .shards
+            .into_vec()
+            .into_iter()
+            .map(|shard| CachePadded(shard.0.with_counter_policy(policy)))
+            .collect();
+
+        Self {
+            config: self.config,
+            shards,
+        }
+    }
+
+    /// Returns the bucket configuration of the histogram.
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Returns the number of shards.
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns the shard for the current thread.
+    fn shard(&self) -> &AtomicHistogram {
+        let shard = SHARD.with(|shard| *shard);
+        &self.shards[shard % self.shards.len()].0
+    }
+
+    /// Increment the bucket that contains the value by one, using the shard
+    /// assigned to the current thread.
+    pub fn increment(&self, value: u64) -> Result<(), Error> {
+        self.add(value, 1)
+    }
+
+    /// Add `count` to the bucket that contains the `value`, using the shard
+    /// assigned to the current thread.
+    pub fn add(&self, value: u64, count: u64) -> Result<(), Error> {
+        self.shard().add(value, count)
+    }
+
+    /// Increment the bucket that contains the value by one, using the shard
+    /// selected by the `hint`. See
+    /// [`add_with_hint`](ShardedAtomicHistogram::add_with_hint).
+    pub fn increment_with_hint(&self, hint: usize, value: u64) -> Result<(), Error> {
+        self.add_with_hint(hint, value, 1)
+    }
+
+    /// Add `count` to the bucket that contains the `value`, using the shard
+    /// selected by the `hint`. The hint is reduced modulo the number of
+    /// shards, so a CPU number or worker index can be used directly.
+    pub fn add_with_hint(&self, hint: usize, value: u64, count: u64) -> Result<(), Error> {
+        self.shards[hint % self.shards.len()].0.add(value, count)
+    }
+
+    /// Sums the shards into a single histogram according to the counter
+    /// policy. Taking a snapshot cannot fail, so with the checked policy a sum
+    /// which overflows saturates, and the summary is dropped if its count
+    /// overflows.
+    fn merge(&self, f: impl Fn(&AtomicHistogram) -> Histogram) -> Histogram {
+        let mut shards = self.shards.iter().map(|shard| f(&shard.0));
+        let mut histogram = shards.next().unwrap();
+        let wrapping = histogram.counter_policy == CounterPolicy::Wrapping;
+        let add = |a: u64, b: u64| {
+            if wrapping {
+                a.wrapping_add(b)
+            } else {
+                a.saturating_add(b)
+            }
+        };
+
+        for shard in shards {
+            for (this, other) in histogram.buckets.iter_mut().zip(shard.buckets.iter()) {
+                *this = add(*this, *other);
+            }
+            histogram.overflow_count = add(histogram.overflow_count, shard.overflow_count);
+            let summary = summary::merge(
+                histogram.counter_policy,
+                histogram.summary(),
+                shard.summary(),
+            );
+            histogram.summary = summary.map(Box::new);
+        }
+
+        histogram
+    }
+
+    // NOTE: once stabilized, `target_has_atomic_load_store` is more correct. https://github.com/rust-lang/rust/issues/94039
+    #[cfg(target_has_atomic = "64")]
+    /// Drains the bucket values of every shard into a new Histogram
+    ///
+    /// Unlike [`load`](ShardedAtomicHistogram::load), this method will reset
+    /// all bucket values to zero. See [`AtomicHistogram::drain`] and
+    /// [`load`](ShardedAtomicHistogram::load) for how the shards are summed.
+    pub fn drain(&self) -> Histogram {
+        self.merge(AtomicHistogram::drain)
+    }
+
+    /// Read the bucket values of every shard and sum them into a new
+    /// `Histogram` according to the [`CounterPolicy`].
+    ///
+    /// With [`CounterPolicy::Checked`], a sum of the shards which overflows a
+    /// counter saturates at `u64::MAX`, since there is no way to reject it,
+    /// and the summary is dropped if its count overflows.
+    pub fn load(&self) -> Histogram {
+        self.merge(AtomicHistogram::load)
+    }
+}
+
+impl std::fmt::Debug for ShardedAtomicHistogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShardedAtomicHistogram")
+            .field("config", &self.config)
+            .field("shards", &self.shards.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn invalid() {
+        assert_eq!(
+            ShardedAtomicHistogram::new(7, 64, 0).map(|_| ()),
+            Err(Error::IncompatibleParameters)
+        );
+    }
+
+    #[test]
+    // Tests that recording from many threads is summed by load
+    fn load() {
+        let histogram = Arc::new(
+            ShardedAtomicHistogram::new(7, 64, 4)
+                .unwrap()
+                .with_summary(),
+        );
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let histogram = histogram.clone();
+                std::thread::spawn(move || {
+                    for value in 1..=100 {
+                        histogram.increment(value).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let snapshot = histogram.load();
+        assert_eq!(snapshot.as_slice().iter().sum::<u64>(), 800);
+        assert_eq!(snapshot.percentile(0.5).unwrap().unwrap().end(), 50);
+        let summary = snapshot.summary().unwrap();
+        assert_eq!(summary.min(), Some(1));
+        assert_eq!(summary.max(), Some(100));
+        assert_eq!(summary.count(), 800);
+    }
+
+    #[cfg(target_has_atomic = "64")]
+    #[test]
+    fn drain() {
+        let histogram = ShardedAtomicHistogram::new(7, 64, 3).unwrap();
+        for hint in 0..6 {
+            histogram.increment_with_hint(hint, 10).unwrap();
+        }
+        histogram.add_with_hint(1, 20, 4).unwrap();
+
+        let snapshot = histogram.drain();
+        assert_eq!(snapshot.as_slice()[10], 6);
+        assert_eq!(snapshot.as_slice()[20], 4);
+        assert!(histogram.load().as_slice().iter().all(|c| *c == 0));
+    }
+
+    #[test]
+    // Tests that the shards are summed according to the counter policy
+    fn counter_policy() {
+        let histogram = ShardedAtomicHistogram::new(7, 64, 2)
+            .unwrap()
+            .with_counter_policy(CounterPolicy::Saturating);
+        histogram.add_with_hint(0, 10, u64::MAX).unwrap();
+        histogram.add_with_hint(1, 10, 2).unwrap();
+        assert_eq!(histogram.load().as_slice()[10], u64::MAX);
+
+        let histogram = ShardedAtomicHistogram::new(7, 64, 2)
+            .unwrap()
+            .with_counter_policy(CounterPolicy::Checked)
+            .with_summary();
+        histogram.add_with_hint(0, 10, u64::MAX).unwrap();
+        histogram.add_with_hint(1, 10, 2).unwrap();
+        let snapshot = histogram.load();
+        assert_eq!(snapshot.as_slice()[10], u64::MAX);
+        assert_eq!(snapshot.summary(), None);
+
+        // draining sums the shards in the same way
+        assert_eq!(histogram.drain(), snapshot);
+        assert_eq!(histogram.load().as_slice()[10], 0);
+    }
+}
@@ -0,0 +1,493 @@
+use crate::bucket::{self, Percentiles};
+use crate::{Bucket, Config, Error, Histogram, SparseHistogram};
+use core::ops::Range;
+
+/// The largest magnitude of a negative `i64`.
+const MIN_MAGNITUDE: u64 = 1 << 63;
+
+/// A histogram of `i64` values, made up of a histogram of the magnitudes of
+/// the negative values and a histogram of the zero and positive values, which
+/// share one [`Config`].
+///
+/// Each value is stored in the bucket of its magnitude on its side, so the
+/// buckets are mirrored around zero with the same relative error on both
+/// sides. The config limits the magnitude of the values, so a max value power
+/// of 64 allows the whole `i64` range.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct SignedHistogram {
+    negative: Histogram,
+    positive: Histogram,
+}
+
+impl SignedHistogram {
+    /// Construct a new histogram from the provided parameters. See the
+    /// documentation for [`crate::Config`] to understand their meaning.
+    pub fn new(grouping_power: u8, max_value_power: u8) -> Result<Self, Error> {
+        let config = Config::new(grouping_power, max_value_power)?;
+
+        Ok(Self::with_config(&config))
+    }
+
+    /// Creates a new histogram using a provided [`crate::Config`].
+    pub fn with_config(config: &Config) -> Self {
+        Self {
+            negative: Histogram::with_config(config),
+            positive: Histogram::with_config(config),
+        }
+    }
+
+    /// Increment the counter for the bucket corresponding to the provided value
+    /// by one (uses wrapping arithmetic on overflow).
+    pub fn increment(&mut self, value: i64) -> Result<(), Error> {
+        self.add(value, 1)
+    }
+
+    /// Add some count to the counter for the bucket corresponding to the
+    /// provided value. The counter uses wrapping arithmetic on overflow.
+    ///
+    /// Returns `Err(Error::OutOfRange)` if the magnitude of the value is
+    /// above the max value of the config.
+    pub fn add(&mut self, value: i64, count: u64) -> Result<(), Error> {
+        if value < 0 {
+            self.negative.add(value.unsigned_abs(), count)
+        } else {
+            self.positive.add(value as u64, count)
+        }
+    }
+
+    /// Returns the bucket configuration of the histogram.
+    pub fn config(&self) -> Config {
+        self.positive.config()
+    }
+
+    /// Returns the histogram of the magnitudes of the negative values.
+    pub fn negative(&self) -> &Histogram {
+        &self.negative
+    }
+
+    /// Returns the histogram of the zero and positive values.
+    pub fn positive(&self) -> &Histogram {
+        &self.positive
+    }
+
+    /// Returns the total number of observations.
+    pub fn total_count(&self) -> u128 {
+        self.negative
+            .as_slice()
+            .iter()
+            .chain(self.positive.as_slice())
+            .map(|c| *c as u128)
+            .sum()
+    }
+
+    /// Return a collection of percentiles from this histogram, across both
+    /// the negative and the positive values.
+    ///
+    /// Each percentile should be in the inclusive range `0.0..=1.0`. For
+    /// example, the 50th percentile (median) can be found using `0.5`.
+    ///
+    /// The results will be sorted by the percentile.
+    pub fn percentiles(&self, percentiles: &[f64]) -> Result<Option<Percentiles<i64>>, Error> {
+        bucket::percentiles(self.iter(), self.total_count(), percentiles)
+    }
+
+    /// Return a single percentile from this histogram.
+    ///
+    /// The percentile should be in the inclusive range `0.0..=1.0`. For
+    /// example, the 50th percentile (median) can be found using `0.5`.
+    pub fn percentile(&self, percentile: f64) -> Result<Option<Bucket<i64>>, Error> {
+        self.percentiles(&[percentile])
+            .map(|v| v.map(|x| x.first().unwrap().1.clone()))
+    }
+
+    /// Adds the other histogram to this histogram and returns the result as a
+    /// new histogram.
+    ///
+    /// An error is returned if the two histograms have incompatible parameters
+    /// or if there is an overflow.
+    pub fn checked_add(&self, other: &SignedHistogram) -> Result<SignedHistogram, Error> {
+        Ok(Self {
+            negative: self.negative.checked_add(&other.negative)?,
+            positive: self.positive.checked_add(&other.positive)?,
+        })
+    }
+
+    /// Adds the other histogram to this histogram and returns the result as a
+    /// new histogram.
+    ///
+    /// An error is returned if the two histograms have incompatible parameters.
+    pub fn wrapping_add(&self, other: &SignedHistogram) -> Result<SignedHistogram, Error> {
+        Ok(Self {
+            negative: self.negative.wrapping_add(&other.negative)?,
+            positive: self.positive.wrapping_add(&other.positive)?,
+        })
+    }
+
+    /// Subtracts the other histogram from this histogram and returns the result
+    /// as a new histogram.
+    ///
+    /// An error is returned if the two histograms have incompatible parameters
+    /// or if there is an overflow.
+    pub fn checked_sub(&self, other: &SignedHistogram) -> Result<SignedHistogram, Error> {
+        Ok(Self {
+            negative: self.negative.checked_sub(&other.negative)?,
+            positive: self.positive.checked_sub(&other.positive)?,
+        })
+    }
+
+    /// Subtracts the other histogram from this histogram and returns the result
+    /// as a new histogram.
+    ///
+    /// An error is returned if the two histograms have incompatible parameters.
+    pub fn wrapping_sub(&self, other: &SignedHistogram) -> Result<SignedHistogram, Error> {
+        Ok(Self {
+            negative: self.negative.wrapping_sub(&other.negative)?,
+            positive: self.positive.wrapping_sub(&other.positive)?,
+        })
+    }
+
+    /// Returns an iterator across the histogram buckets in ascending order of
+    /// their values, from the most negative to the most positive. Only the
+    /// buckets which can hold an `i64` value are included.
+    pub fn iter(&self) -> Iter<'_> {
+        let config = self.config();
+        let (negative, positive) = bounds(&config);
+
+        Iter {
+            config,
+            negative: Side {
+                index: None,
+                count: self.negative.as_slice(),
+                range: 1..negative + 1,
+            },
+            positive: Side {
+                index: None,
+                count: self.positive.as_slice(),
+                range: 0..positive + 1,
+            },
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a SignedHistogram {
+    type Item = Bucket<i64>;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl From<&SparseSignedHistogram> for SignedHistogram {
+    fn from(other: &SparseSignedHistogram) -> Self {
+        Self {
+            negative: Histogram::from(&other.negative),
+            positive: Histogram::from(&other.positive),
+        }
+    }
+}
+
+/// A sparse representation of a [`SignedHistogram`], which only stores the
+/// non-zero buckets of each side.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct SparseSignedHistogram {
+    negative: SparseHistogram,
+    positive: SparseHistogram,
+}
+
+impl SparseSignedHistogram {
+    /// Creates a sparse signed histogram from a histogram of the magnitudes of
+    /// the negative values and a histogram of the zero and positive values.
+    ///
+    /// Returns `Err(Error::IncompatibleParameters)` if the histograms have
+    /// different configs, and `Err(Error::OutOfRange)` if either has a
+    /// non-zero bucket which cannot hold an `i64` value on its side, such as
+    /// the zero bucket of the negative side.
+    pub fn from_parts(negative: SparseHistogram, positive: SparseHistogram) -> Result<Self, Error> {
+        if negative.config != positive.config {
+            return Err(Error::IncompatibleParameters);
+        }
+
+        let (max_negative, max_positive) = bounds(&negative.config);
+        let in_range = |index: &[u32], range: Range<usize>| {
+            index.iter().all(|i| range.contains(&(*i as usize)))
+        };
+        if !in_range(&negative.index, 1..max_negative + 1)
+            || !in_range(&positive.index, 0..max_positive + 1)
+        {
+            return Err(Error::OutOfRange);
+        }
+
+        Ok(Self { negative, positive })
+    }
+
+    /// Consumes the histogram, returning the negative and positive sides.
+    pub fn into_parts(self) -> (SparseHistogram, SparseHistogram) {
+        (self.negative, self.positive)
+    }
+
+    /// Returns the bucket configuration of the histogram.
+    pub fn config(&self) -> Config {
+        self.positive.config()
+    }
+
+    /// Returns the histogram of the magnitudes of the negative values.
+    pub fn negative(&self) -> &SparseHistogram {
+        &self.negative
+    }
+
+    /// Returns the histogram of the zero and positive values.
+    pub fn positive(&self) -> &SparseHistogram {
+        &self.positive
+    }
+
+    /// Returns the total number of observations.
+    pub fn total_count(&self) -> u128 {
+        self.negative
+            .count
+            .iter()
+            .chain(&self.positive.count)
+            .map(|c| *c as u128)
+            .sum()
+    }
+
+    /// Return a collection of percentiles from this histogram. See
+    /// [`SignedHistogram::percentiles`] for details.
+    pub fn percentiles(&self, percentiles: &[f64]) -> Result<Option<Percentiles<i64>>, Error> {
+        bucket::percentiles(self.iter(), self.total_count(), percentiles)
+    }
+
+    /// Return a single percentile from this histogram. See
+    /// [`SignedHistogram::percentile`] for details.
+    pub fn percentile(&self, percentile: f64) -> Result<Option<Bucket<i64>>, Error> {
+        self.percentiles(&[percentile])
+            .map(|v| v.map(|x| x.first().unwrap().1.clone()))
+    }
+
+    /// Returns an iterator across the non-zero histogram buckets in ascending
+    /// order of their values.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            config: self.config(),
+            negative: Side {
+                index: Some(&self.negative.index),
+                count: &self.negative.count,
+                range: 0..self.negative.index.len(),
+            },
+            positive: Side {
+                index: Some(&self.positive.index),
+                count: &self.positive.count,
+                range: 0..self.positive.index.len(),
+            },
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a SparseSignedHistogram {
+    type Item = Bucket<i64>;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl From<&SignedHistogram> for SparseSignedHistogram {
+    fn from(other: &SignedHistogram) -> Self {
+        Self {
+            negative: SparseHistogram::from(&other.negative),
+            positive: SparseHistogram::from(&other.positive),
+        }
+    }
+}
+
+/// Returns the indices of the buckets holding the largest negative magnitude
+/// and the largest positive value that fit in an `i64`.
+fn bounds(config: &Config) -> (usize, usize) {
+    let max = config.index_to_upper_bound(config.total_buckets() - 1);
+    let negative = config.value_to_index(max.min(MIN_MAGNITUDE)).unwrap();
+    let positive = config.value_to_index(max.min(i64::MAX as u64)).unwrap();
+    (negative, positive)
+}
+
+/// Negates a magnitude, saturating at `i64::MIN`.
+fn negate(magnitude: u64) -> i64 {
+    if magnitude >= MIN_MAGNITUDE {
+        i64::MIN
+    } else {
+        -(magnitude as i64)
+    }
+}
+
+/// The buckets of one side of a signed histogram. Dense sides have no index,
+/// and use the position of each count as its bucket index.
+struct Side<'a> {
+    index: Option<&'a [u32]>,
+    count: &'a [u64],
+    range: Range<usize>,
+}
+
+impl Side<'_> {
+    fn bucket(&self, position: usize) -> (usize, u64) {
+        let index = self
+            .index
+            .map_or(position, |index| index[position] as usize);
+        (index, self.count[position])
+    }
+}
+
+/// An iterator across the buckets of a [`SignedHistogram`] or a
+/// [`SparseSignedHistogram`].
+pub struct Iter<'a> {
+    config: Config,
+    negative: Side<'a>,
+    positive: Side<'a>,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Bucket<i64>;
+
+    fn next(&mut self) -> Option<<Self as std::iter::Iterator>::Item> {
+        // the negative side is scanned from the largest magnitude down
+        if let Some(position) = self.negative.range.next_back() {
+            let (index, count) = self.negative.bucket(position);
+            let range = self.config.index_to_range(index);
+            return Some(Bucket {
+                count,
+                range: negate(*range.end())..=negate(*range.start()),
+            });
+        }
+
+        let position = self.positive.range.next()?;
+        let (index, count) = self.positive.bucket(position);
+        let range = self.config.index_to_range(index);
+        let clamp = |value: u64| value.min(i64::MAX as u64) as i64;
+        Some(Bucket {
+            count,
+            range: clamp(*range.start())..=clamp(*range.end()),
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.len();
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {
+    fn len(&self) -> usize {
+        self.negative.range.len() + self.positive.range.len()
+    }
+}
+
+impl std::iter::FusedIterator for Iter<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let mut histogram = SignedHistogram::new(4, 10).unwrap();
+        for value in -100..=100 {
+            histogram.increment(value).unwrap();
+        }
+        assert_eq!(histogram.total_count(), 201);
+        assert_eq!(histogram.increment(1024), Err(Error::OutOfRange));
+        assert_eq!(histogram.increment(-1024), Err(Error::OutOfRange));
+
+        let percentiles = histogram.percentiles(&[0.0, 0.5, 1.0]).unwrap().unwrap();
+        assert_eq!(percentiles[0].1.range(), -103..=-100);
+        assert_eq!(percentiles[1].1.range(), 0..=0);
+        assert_eq!(percentiles[2].1.range(), 100..=103);
+
+        // the values below zero are the lower half
+        assert_eq!(
+            histogram.percentile(0.49).unwrap().unwrap().range(),
+            -2..=-2
+        );
+        assert_eq!(histogram.percentile(1.5), Err(Error::InvalidPercentile));
+
+        let sparse = SparseSignedHistogram::from(&histogram);
+        assert_eq!(
+            sparse.percentiles(&[0.0, 0.5, 1.0]).unwrap().unwrap(),
+            percentiles
+        );
+        assert_eq!(SignedHistogram::from(&sparse), histogram);
+    }
+
+    #[test]
+    fn iter() {
+        let mut histogram = SignedHistogram::new(1, 4).unwrap();
+        histogram.add(-15, 2).unwrap();
+        histogram.increment(0).unwrap();
+        histogram.increment(3).unwrap();
+
+        let buckets: Vec<_> = histogram.iter().collect();
+        // the zero bucket is only on the positive side
+        assert_eq!(buckets.len(), 2 * histogram.config().total_buckets() - 1);
+        assert!(buckets.windows(2).all(|w| w[0].end() < w[1].start()));
+        assert_eq!(buckets[0].range(), -15..=-12);
+        assert_eq!(buckets[0].count(), 2);
+
+        let sparse = SparseSignedHistogram::from(&histogram);
+        let buckets: Vec<_> = sparse.iter().map(|b| (b.range(), b.count())).collect();
+        assert_eq!(buckets, vec![(-15..=-12, 2), (0..=0, 1), (3..=3, 1)]);
+    }
+
+    #[test]
+    fn extremes() {
+        let mut histogram = SignedHistogram::new(7, 64).unwrap();
+        histogram.increment(i64::MIN).unwrap();
+        histogram.increment(i64::MAX).unwrap();
+
+        let min = histogram.percentile(0.0).unwrap().unwrap();
+        let max = histogram.percentile(1.0).unwrap().unwrap();
+        assert_eq!(min.start(), i64::MIN);
+        assert!(min.range().contains(&i64::MIN));
+        assert_eq!(max.end(), i64::MAX);
+        assert!(max.range().contains(&i64::MAX));
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut a = SignedHistogram::new(4, 10).unwrap();
+        let mut b = SignedHistogram::new(4, 10).unwrap();
+        a.increment(-5).unwrap();
+        a.increment(5).unwrap();
+        b.increment(-5).unwrap();
+
+        let sum = a.checked_add(&b).unwrap();
+        assert_eq!(sum.total_count(), 3);
+        assert_eq!(sum.percentile(0.5).unwrap().unwrap().range(), -5..=-5);
+        assert_eq!(sum.checked_sub(&b).unwrap(), a);
+        assert_eq!(b.checked_sub(&a), Err(Error::Underflow));
+        assert_eq!(a.wrapping_add(&b).unwrap(), sum);
+
+        let c = SignedHistogram::new(3, 10).unwrap();
+        assert_eq!(a.checked_add(&c), Err(Error::IncompatibleParameters));
+    }
+
+    #[test]
+    fn from_parts() {
+        let config = Config::new(2, 8).unwrap();
+        let zero = SparseHistogram::from_parts(config, vec![0], vec![1]).unwrap();
+        let empty = SparseHistogram::with_config(&config);
+
+        assert_eq!(
+            SparseSignedHistogram::from_parts(zero.clone(), empty.clone()),
+            Err(Error::OutOfRange)
+        );
+        assert_eq!(
+            SparseSignedHistogram::from_parts(empty.clone(), SparseHistogram::new(3, 8).unwrap()),
+            Err(Error::IncompatibleParameters)
+        );
+
+        let histogram = SparseSignedHistogram::from_parts(empty, zero).unwrap();
+        assert_eq!(histogram.percentile(0.5).unwrap().unwrap().range(), 0..=0);
+    }
+}
@@ -1,4 +1,9 @@
-use crate::{Bucket, Config, Error, Histogram};
+use crate::interpolation::{self, Interpolation};
+use crate::prometheus;
+use crate::rank::{self, Rank};
+use crate::statistics::{self, Representative, Statistics};
+use crate::summary::{checked_merge, wrapping_merge};
+use crate::{Bucket, Config, CounterPolicy, Error, Histogram, RangePolicy, Summary};
 
 /// A sparse, columnar representation of a histogram.
 ///
@@ -13,6 +18,26 @@ pub struct SparseHistogram {
     pub(crate) config: Config,
     pub(crate) index: Vec<u32>,
     pub(crate) count: Vec<u64>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub(crate) summary: Option<Summary>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "RangePolicy::is_default")
+    )]
+    pub(crate) range_policy: RangePolicy,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "crate::range::is_zero")
+    )]
+    pub(crate) overflow_count: u64,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "CounterPolicy::is_default")
+    )]
+    pub(crate) counter_policy: CounterPolicy,
 }
 
 impl SparseHistogram {
@@ -30,6 +55,10 @@ impl SparseHistogram {
             config: *config,
             index: Vec::new(),
             count: Vec::new(),
+            summary: None,
+            range_policy: RangePolicy::Error,
+            overflow_count: 0,
+            counter_policy: CounterPolicy::Wrapping,
         }
     }
 
@@ -68,6 +97,10 @@ impl SparseHistogram {
             config,
             index,
             count,
+            summary: None,
+            range_policy: RangePolicy::Error,
+            overflow_count: 0,
+            counter_policy: CounterPolicy::Wrapping,
         })
     }
 
@@ -91,6 +124,42 @@ impl SparseHistogram {
         &self.count
     }
 
+    /// Returns the exact summary of the recorded values, or `None` if the
+    /// histogram this was created from did not track a summary.
+    pub fn summary(&self) -> Option<Summary> {
+        self.summary
+    }
+
+    /// Sets the range policy, which is carried into a [`Histogram`] converted
+    /// from this histogram. See [`Histogram::with_range_policy`].
+    pub fn with_range_policy(mut self, policy: RangePolicy) -> Self {
+        self.range_policy = policy;
+        self
+    }
+
+    /// Returns the range policy of the histogram this was created from.
+    pub fn range_policy(&self) -> RangePolicy {
+        self.range_policy
+    }
+
+    /// Sets the counter policy, which is carried into a [`Histogram`]
+    /// converted from this histogram. See [`Histogram::with_counter_policy`].
+    pub fn with_counter_policy(mut self, policy: CounterPolicy) -> Self {
+        self.counter_policy = policy;
+        self
+    }
+
+    /// Returns the counter policy of the histogram this was created from.
+    pub fn counter_policy(&self) -> CounterPolicy {
+        self.counter_policy
+    }
+
+    /// Returns the count of values above the max value of the config. See
+    /// [`Histogram::overflow_count`].
+    pub fn overflow_count(&self) -> u64 {
+        self.overflow_count
+    }
+
     /// Helper function to store a bucket in the histogram.
     fn add_bucket(&mut self, idx: u32, n: u64) {
         if n != 0 {
@@ -104,6 +173,10 @@ impl SparseHistogram {
     ///
     /// Returns `Err(Error::IncompatibleParameters)` if the configs don't match,
     /// or `Err(Error::Overflow)` if any bucket overflows.
+    ///
+    /// The result only carries a summary if both histograms have one and their
+    /// merged count does not overflow. The overflow counts are added, and the
+    /// result keeps the range and counter policies of this histogram.
     #[allow(clippy::comparison_chain)]
     pub fn checked_add(&self, h: &SparseHistogram) -> Result<SparseHistogram, Error> {
         if self.config != h.config {
@@ -111,6 +184,13 @@ impl SparseHistogram {
         }
 
         let mut histogram = SparseHistogram::with_config(&self.config);
+        histogram.summary = checked_merge(self.summary, h.summary);
+        histogram.range_policy = self.range_policy;
+        histogram.counter_policy = self.counter_policy;
+        histogram.overflow_count = self
+            .overflow_count
+            .checked_add(h.overflow_count)
+            .ok_or(Error::Overflow)?;
 
         let (mut i, mut j) = (0, 0);
         while i < self.index.len() && j < h.index.len() {
@@ -148,6 +228,10 @@ impl SparseHistogram {
     ///
     /// Returns `Err(Error::IncompatibleParameters)` if the configs don't match.
     /// Buckets which have values in both histograms are allowed to wrap.
+    ///
+    /// The result only carries a summary if both histograms have one. The
+    /// overflow counts are added, and the result keeps the range and counter
+    /// policies of this histogram.
     #[allow(clippy::comparison_chain)]
     pub fn wrapping_add(&self, h: &SparseHistogram) -> Result<SparseHistogram, Error> {
         if self.config != h.config {
@@ -155,6 +239,10 @@ impl SparseHistogram {
         }
 
         let mut histogram = SparseHistogram::with_config(&self.config);
+        histogram.summary = wrapping_merge(self.summary, h.summary);
+        histogram.range_policy = self.range_policy;
+        histogram.counter_policy = self.counter_policy;
+        histogram.overflow_count = self.overflow_count.wrapping_add(h.overflow_count);
 
         // Sort and merge buckets from both histograms
         let (mut i, mut j) = (0, 0);
@@ -198,6 +286,9 @@ impl SparseHistogram {
     /// `Err(Error::InvalidSubset)` if the other histogram has buckets not
     /// present in this one, or `Err(Error::Underflow)` if any bucket would
     /// underflow.
+    ///
+    /// The result has no summary since the exact minimum and maximum cannot be
+    /// recovered by subtraction. The overflow counts are subtracted.
     #[allow(clippy::comparison_chain)]
     pub fn checked_sub(&self, h: &SparseHistogram) -> Result<SparseHistogram, Error> {
         if self.config != h.config {
@@ -205,6 +296,12 @@ impl SparseHistogram {
         }
 
         let mut histogram = SparseHistogram::with_config(&self.config);
+        histogram.range_policy = self.range_policy;
+        histogram.counter_policy = self.counter_policy;
+        histogram.overflow_count = self
+            .overflow_count
+            .checked_sub(h.overflow_count)
+            .ok_or(Error::Underflow)?;
 
         // Sort and merge buckets from both histograms
         let (mut i, mut j) = (0, 0);
@@ -249,6 +346,9 @@ impl SparseHistogram {
     /// or `Err(Error::InvalidSubset)` if the other histogram has buckets not
     /// present in this one.
     /// Buckets are allowed to wrap on underflow.
+    ///
+    /// The result has no summary since the exact minimum and maximum cannot be
+    /// recovered by subtraction. The overflow counts are subtracted.
     #[allow(clippy::comparison_chain)]
     pub fn wrapping_sub(&self, h: &SparseHistogram) -> Result<SparseHistogram, Error> {
         if self.config != h.config {
@@ -256,6 +356,9 @@ impl SparseHistogram {
         }
 
         let mut histogram = SparseHistogram::with_config(&self.config);
+        histogram.range_policy = self.range_policy;
+        histogram.counter_policy = self.counter_policy;
+        histogram.overflow_count = self.overflow_count.wrapping_sub(h.overflow_count);
 
         let (mut i, mut j) = (0, 0);
         while i < self.index.len() && j < h.index.len() {
@@ -358,6 +461,108 @@ impl SparseHistogram {
             .map(|v| v.map(|x| x.first().unwrap().1.clone()))
     }
 
+    /// Return a collection of percentiles from this histogram, each estimated
+    /// as a single value from its bucket according to the `interpolation`.
+    ///
+    /// Each percentile should be in the inclusive range `0.0..=1.0`. For
+    /// example, the 50th percentile (median) can be found using `0.5`.
+    ///
+    /// The results will be sorted by the percentile.
+    pub fn percentile_values(
+        &self,
+        percentiles: &[f64],
+        interpolation: Interpolation,
+    ) -> Result<Option<Vec<(f64, f64)>>, Error> {
+        interpolation::percentile_values(self, percentiles, interpolation)
+    }
+
+    /// Return a single percentile from this histogram, estimated as a single
+    /// value from its bucket according to the `interpolation`.
+    ///
+    /// The percentile should be in the inclusive range `0.0..=1.0`. For
+    /// example, the 50th percentile (median) can be found using `0.5`.
+    pub fn percentile_value(
+        &self,
+        percentile: f64,
+        interpolation: Interpolation,
+    ) -> Result<Option<f64>, Error> {
+        self.percentile_values(&[percentile], interpolation)
+            .map(|v| v.map(|x| x.first().unwrap().1))
+    }
+
+    /// Returns the fraction of observations which are less than or equal to
+    /// the value, or `None` if the histogram is empty. This is the inverse of
+    /// a percentile query and is also known as the cumulative distribution
+    /// function.
+    ///
+    /// Returns `Err(Error::OutOfRange)` if the value cannot be stored in the
+    /// histogram.
+    pub fn rank(&self, value: u64) -> Result<Option<Rank>, Error> {
+        self.ranks(&[value])
+            .map(|v| v.map(|x| x.first().unwrap().1))
+    }
+
+    /// Return the ranks of a collection of values from this histogram. See
+    /// [`rank`](Self::rank) for details.
+    ///
+    /// The results will be sorted by the value.
+    pub fn ranks(&self, values: &[u64]) -> Result<Option<Vec<(u64, Rank)>>, Error> {
+        rank::ranks(self, &self.config, values)
+    }
+
+    /// Returns statistics estimated from the bucket counts, or `None` if the
+    /// histogram is empty. See [`Statistics`] for details about the accuracy
+    /// of the estimates.
+    pub fn statistics(&self, representative: Representative) -> Option<Statistics> {
+        statistics::statistics(self, &self.config, representative)
+    }
+
+    /// Renders the histogram as a Prometheus classic histogram in the text
+    /// exposition format, with a `_bucket` series for the upper bound of each
+    /// non-empty bucket followed by `+Inf`, and the `_sum` and `_count`
+    /// series. The `le` label is added after the provided `labels`.
+    ///
+    /// The sum is exact if the histogram tracks a [`Summary`], and is
+    /// otherwise estimated from the bucket midpoints. The overflow count is
+    /// included in `+Inf` and `_count`, but an estimated sum leaves out the
+    /// values it counts, since they have no bucket, which skews
+    /// `_sum / _count` low.
+    ///
+    /// The `# HELP` and `# TYPE` lines are not included, since they should
+    /// appear once for all the histograms sharing a metric name. The metric
+    /// and label names are not validated.
+    pub fn to_prometheus(&self, name: &str, labels: &[(&str, &str)]) -> String {
+        // there are no boundaries to validate
+        prometheus::exposition(self, self.summary, self.overflow_count, name, labels, None).unwrap()
+    }
+
+    /// Renders the histogram as a Prometheus classic histogram using the
+    /// provided `le` boundaries. See
+    /// [`to_prometheus`](Self::to_prometheus) for details.
+    ///
+    /// Each bucket is counted in the first boundary at or above its upper
+    /// bound. The cumulative counts are exact when every boundary is the
+    /// upper bound of a bucket. Otherwise, the observations in a bucket which
+    /// spans a boundary are counted in the next boundary.
+    ///
+    /// Returns `Err(Error::IncompatibleParameters)` if the boundaries are not
+    /// in strictly ascending order.
+    pub fn to_prometheus_with_boundaries(
+        &self,
+        name: &str,
+        labels: &[(&str, &str)],
+        boundaries: &[u64],
+    ) -> Result<String, Error> {
+        prometheus::exposition(
+            self,
+            self.summary,
+            self.overflow_count,
+            name,
+            labels,
+            Some(boundaries),
+        )
+    }
+
     /// Returns a new histogram with a reduced grouping power. The reduced
     /// grouping power should lie in the range (0..existing grouping power).
     ///
@@ -405,6 +610,10 @@ impl SparseHistogram {
 
         // Add the final aggregated bucket
         histogram.add_bucket(aggregating_idx, aggregating_count);
+        histogram.summary = self.summary;
+        histogram.range_policy = self.range_policy;
+        histogram.counter_policy = self.counter_policy;
+        histogram.overflow_count = self.overflow_count;
 
         Ok(histogram)
     }
@@ -482,6 +691,10 @@ impl From<&Histogram> for SparseHistogram {
             config: histogram.config(),
             index,
             count,
+            summary: histogram.summary(),
+            range_policy: histogram.range_policy,
+            overflow_count: histogram.overflow_count,
+            counter_policy: histogram.counter_policy,
         }
     }
 }
@@ -701,4 +914,28 @@ mod tests {
             compare_histograms(&h1, &h2);
         }
     }
+
+    #[test]
+    fn summary() {
+        let mut h1 = Histogram::new(7, 32).unwrap().with_summary();
+        let mut h2 = Histogram::new(7, 32).unwrap().with_summary();
+        let _ = h1.add(3, 2);
+        let _ = h2.add(300, 1);
+
+        let s1 = SparseHistogram::from(&h1);
+        let s2 = SparseHistogram::from(&h2);
+
+        let merged = s1.checked_add(&s2).unwrap().summary().unwrap();
+        assert_eq!(merged.min(), Some(3));
+        assert_eq!(merged.max(), Some(300));
+        assert_eq!(merged.sum(), 306);
+        assert_eq!(merged.count(), 3);
+        assert_eq!(s1.wrapping_add(&s2).unwrap().summary(), Some(merged));
+
+        assert_eq!(s1.checked_sub(&s1).unwrap().summary(), None);
+        assert_eq!(s2.downsample(2).unwrap().summary(), h2.summary());
+
+        let plain = SparseHistogram::new(7, 32).unwrap();
+        assert_eq!(s1.checked_add(&plain).unwrap().summary(), None);
+    }
 }
@@ -1,4 +1,9 @@
-use crate::{Bucket, Config, Error, SparseHistogram};
+use crate::interpolation::{self, Interpolation};
+use crate::prometheus;
+use crate::rank::{self, Rank};
+use crate::statistics::{self, Representative, Statistics};
+use crate::summary::{checked_merge, wrapping_merge};
+use crate::{Bucket, Config, CounterPolicy, Error, RangePolicy, SparseHistogram, Summary};
 
 /// A histogram that uses plain 64bit counters for each bucket.
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -7,6 +12,26 @@ use crate::{Bucket, Config, Error, SparseHistogram};
 pub struct Histogram {
     pub(crate) config: Config,
     pub(crate) buckets: Box<[u64]>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub(crate) summary: Option<Box<Summary>>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "RangePolicy::is_default")
+    )]
+    pub(crate) range_policy: RangePolicy,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "crate::range::is_zero")
+    )]
+    pub(crate) overflow_count: u64,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "CounterPolicy::is_default")
+    )]
+    pub(crate) counter_policy: CounterPolicy,
 }
 
 impl Histogram {
@@ -25,9 +50,38 @@ impl Histogram {
         Self {
             config: *config,
             buckets,
+            summary: None,
+            range_policy: RangePolicy::Error,
+            overflow_count: 0,
+            counter_policy: CounterPolicy::Wrapping,
         }
     }
 
+    /// Enables tracking of the exact minimum, maximum, sum, and count of the
+    /// recorded values. See [`Histogram::summary`].
+    ///
+    /// Only values recorded after the summary is enabled are reflected in it,
+    /// so this should be called on a newly created histogram.
+    pub fn with_summary(mut self) -> Self {
+        self.summary = Some(Box::new(Summary::new()));
+        self
+    }
+
+    /// Sets how values above the max value of the config are recorded. By
+    /// default, recording them returns `Err(Error::OutOfRange)`.
+    pub fn with_range_policy(mut self, policy: RangePolicy) -> Self {
+        self.range_policy = policy;
+        self
+    }
+
+    /// Sets how a counter which would overflow is updated when a value is
+    /// recorded with [`add`](Histogram::add) or
+    /// [`increment`](Histogram::increment). By default, the counters wrap.
+    pub fn with_counter_policy(mut self, policy: CounterPolicy) -> Self {
+        self.counter_policy = policy;
+        self
+    }
+
     /// Creates a new histogram using a provided [`crate::Config`] and the
     /// provided collection of buckets.
     pub fn from_buckets(
@@ -44,23 +98,88 @@ impl Histogram {
         Ok(Self {
             config,
             buckets: buckets.into(),
+            summary: None,
+            range_policy: RangePolicy::Error,
+            overflow_count: 0,
+            counter_policy: CounterPolicy::Wrapping,
         })
     }
 
     /// Increment the counter for the bucket corresponding to the provided value
-    /// by one (uses wrapping arithmetic on overflow).
+    /// by one. See [`add`](Histogram::add).
     pub fn increment(&mut self, value: u64) -> Result<(), Error> {
         self.add(value, 1)
     }
 
     /// Add some count to the counter for the bucket corresponding to the
-    /// provided value. The counter uses wrapping arithmetic on overflow.
+    /// provided value. The counter is updated according to the
+    /// [`CounterPolicy`], which uses wrapping arithmetic on overflow by
+    /// default.
+    ///
+    /// Values above the max value of the config are handled according to the
+    /// [`RangePolicy`]. The summary records the exact value either way.
     pub fn add(&mut self, value: u64, count: u64) -> Result<(), Error> {
-        let index = self.config.value_to_index(value)?;
-        self.buckets[index] = self.buckets[index].wrapping_add(count);
+        self.record(value, count, self.counter_policy)
+    }
+
+    /// Add some count to the counter for the bucket corresponding to the
+    /// provided value, returning `Err(Error::Overflow)` and leaving the
+    /// histogram unchanged if the counter would overflow.
+    pub fn checked_add_value(&mut self, value: u64, count: u64) -> Result<(), Error> {
+        self.record(value, count, CounterPolicy::Checked)
+    }
+
+    /// Add some count to the counter for the bucket corresponding to the
+    /// provided value, stopping at `u64::MAX` if the counter would overflow.
+    pub fn saturating_add_value(&mut self, value: u64, count: u64) -> Result<(), Error> {
+        self.record(value, count, CounterPolicy::Saturating)
+    }
+
+    /// Add some count to the counter for the bucket corresponding to the
+    /// provided value, wrapping around if the counter would overflow.
+    pub fn wrapping_add_value(&mut self, value: u64, count: u64) -> Result<(), Error> {
+        self.record(value, count, CounterPolicy::Wrapping)
+    }
+
+    fn record(&mut self, value: u64, count: u64, policy: CounterPolicy) -> Result<(), Error> {
+        let counter = match self.range_policy.index(&self.config, value)? {
+            Some(index) => &mut self.buckets[index],
+            None => &mut self.overflow_count,
+        };
+        *counter = policy.add(*counter, count)?;
+        if let Some(summary) = &mut self.summary {
+            summary.record(value, count);
+        }
         Ok(())
     }
 
+    /// Returns the exact summary of the recorded values, or `None` if summary
+    /// tracking was not enabled with [`Histogram::with_summary`].
+    ///
+    /// Changes made directly to the counters through
+    /// [`Histogram::as_mut_slice`] are not reflected in the summary.
+    pub fn summary(&self) -> Option<Summary> {
+        self.summary.as_deref().copied()
+    }
+
+    /// Returns how values above the max value of the config are recorded.
+    pub fn range_policy(&self) -> RangePolicy {
+        self.range_policy
+    }
+
+    /// Returns how a counter which would overflow is updated when a value is
+    /// recorded.
+    pub fn counter_policy(&self) -> CounterPolicy {
+        self.counter_policy
+    }
+
+    /// Returns the count of values above the max value of the config which
+    /// were recorded with [`RangePolicy::Count`]. The counter is updated
+    /// according to the [`CounterPolicy`], like the buckets.
+    pub fn overflow_count(&self) -> u64 {
+        self.overflow_count
+    }
+
     /// Get a reference to the raw counters.
     pub fn as_slice(&self) -> &[u64] {
         &self.buckets
@@ -145,6 +264,116 @@ impl Histogram {
             .map(|v| v.map(|x| x.first().unwrap().1.clone()))
     }
 
+    /// Return a collection of percentiles from this histogram, each estimated
+    /// as a single value from its bucket according to the `interpolation`.
+    ///
+    /// Each percentile should be in the inclusive range `0.0..=1.0`. For
+    /// example, the 50th percentile (median) can be found using `0.5`.
+    ///
+    /// The results will be sorted by the percentile.
+    pub fn percentile_values(
+        &self,
+        percentiles: &[f64],
+        interpolation: Interpolation,
+    ) -> Result<Option<Vec<(f64, f64)>>, Error> {
+        interpolation::percentile_values(self, percentiles, interpolation)
+    }
+
+    /// Return a single percentile from this histogram, estimated as a single
+    /// value from its bucket according to the `interpolation`.
+    ///
+    /// The percentile should be in the inclusive range `0.0..=1.0`. For
+    /// example, the 50th percentile (median) can be found using `0.5`.
+    pub fn percentile_value(
+        &self,
+        percentile: f64,
+        interpolation: Interpolation,
+    ) -> Result<Option<f64>, Error> {
+        self.percentile_values(&[percentile], interpolation)
+            .map(|v| v.map(|x| x.first().unwrap().1))
+    }
+
+    /// Returns the fraction of observations which are less than or equal to
+    /// the value, or `None` if the histogram is empty. This is the inverse of
+    /// a percentile query and is also known as the cumulative distribution
+    /// function.
+    ///
+    /// Returns `Err(Error::OutOfRange)` if the value cannot be stored in the
+    /// histogram.
+    pub fn rank(&self, value: u64) -> Result<Option<Rank>, Error> {
+        self.ranks(&[value])
+            .map(|v| v.map(|x| x.first().unwrap().1))
+    }
+
+    /// Return the ranks of a collection of values from this histogram. See
+    /// [`rank`](Self::rank) for details.
+    ///
+    /// The results will be sorted by the value.
+    pub fn ranks(&self, values: &[u64]) -> Result<Option<Vec<(u64, Rank)>>, Error> {
+        rank::ranks(self, &self.config, values)
+    }
+
+    /// Returns statistics estimated from the bucket counts, or `None` if the
+    /// histogram is empty. See [`Statistics`] for details about the accuracy
+    /// of the estimates.
+    pub fn statistics(&self, representative: Representative) -> Option<Statistics> {
+        statistics::statistics(self, &self.config, representative)
+    }
+
+    /// Renders the histogram as a Prometheus classic histogram in the text
+    /// exposition format, with a `_bucket` series for the upper bound of each
+    /// non-empty bucket followed by `+Inf`, and the `_sum` and `_count`
+    /// series. The `le` label is added after the provided `labels`.
+    ///
+    /// The sum is exact if the histogram tracks a [`Summary`], and is
+    /// otherwise estimated from the bucket midpoints. The overflow count is
+    /// included in `+Inf` and `_count`, but an estimated sum leaves out the
+    /// values it counts, since they have no bucket, which skews
+    /// `_sum / _count` low.
+    ///
+    /// The `# HELP` and `# TYPE` lines are not included, since they should
+    /// appear once for all the histograms sharing a metric name. The metric
+    /// and label names are not validated.
+    pub fn to_prometheus(&self, name: &str, labels: &[(&str, &str)]) -> String {
+        // there are no boundaries to validate
+        prometheus::exposition(
+            self,
+            self.summary(),
+            self.overflow_count,
+            name,
+            labels,
+            None,
+        )
+        .unwrap()
+    }
+
+    /// Renders the histogram as a Prometheus classic histogram using the
+    /// provided `le` boundaries. See
+    /// [`to_prometheus`](Self::to_prometheus) for details.
+    ///
+    /// Each bucket is counted in the first boundary at or above its upper
+    /// bound. The cumulative counts are exact when every boundary is the
+    /// upper bound of a bucket. Otherwise, the observations in a bucket which
+    /// spans a boundary are counted in the next boundary.
+    ///
+    /// Returns `Err(Error::IncompatibleParameters)` if the boundaries are not
+    /// in strictly ascending order.
+    pub fn to_prometheus_with_boundaries(
+        &self,
+        name: &str,
+        labels: &[(&str, &str)],
+        boundaries: &[u64],
+    ) -> Result<String, Error> {
+        prometheus::exposition(
+            self,
+            self.summary(),
+            self.overflow_count,
+            name,
+            labels,
+            Some(boundaries),
+        )
+    }
+
     /// Returns a new histogram with a reduced grouping power. The reduced
     /// grouping power should lie in the range (0..existing grouping power).
     ///
@@ -173,6 +402,10 @@ impl Histogram {
                 histogram.add(val, *n)?;
             }
         }
+        histogram.summary = self.summary.clone();
+        histogram.range_policy = self.range_policy;
+        histogram.overflow_count = self.overflow_count;
+        histogram.counter_policy = self.counter_policy;
 
         Ok(histogram)
     }
@@ -182,12 +415,21 @@ impl Histogram {
     ///
     /// An error is returned if the two histograms have incompatible parameters
     /// or if there is an overflow.
+    ///
+    /// The result only carries a summary if both histograms have one and their
+    /// merged count does not overflow. The overflow counts are added, and the
+    /// result keeps the range and counter policies of this histogram.
     pub fn checked_add(&self, other: &Histogram) -> Result<Histogram, Error> {
         if self.config != other.config {
             return Err(Error::IncompatibleParameters);
         }
 
         let mut result = self.clone();
+        result.summary = checked_merge(self.summary(), other.summary()).map(Box::new);
+        result.overflow_count = self
+            .overflow_count
+            .checked_add(other.overflow_count)
+            .ok_or(Error::Overflow)?;
 
         for (this, other) in result.buckets.iter_mut().zip(other.buckets.iter()) {
             *this = this.checked_add(*other).ok_or(Error::Overflow)?;
@@ -200,12 +442,18 @@ impl Histogram {
     /// new histogram.
     ///
     /// An error is returned if the two histograms have incompatible parameters.
+    ///
+    /// The result only carries a summary if both histograms have one. The
+    /// overflow counts are added, and the result keeps the range and counter
+    /// policies of this histogram.
     pub fn wrapping_add(&self, other: &Histogram) -> Result<Histogram, Error> {
         if self.config != other.config {
             return Err(Error::IncompatibleParameters);
         }
 
         let mut result = self.clone();
+        result.summary = wrapping_merge(self.summary(), other.summary()).map(Box::new);
+        result.overflow_count = self.overflow_count.wrapping_add(other.overflow_count);
 
         for (this, other) in result.buckets.iter_mut().zip(other.buckets.iter()) {
             *this = this.wrapping_add(*other);
@@ -219,12 +467,20 @@ impl Histogram {
     ///
     /// An error is returned if the two histograms have incompatible parameters
     /// or if there is an overflow.
+    ///
+    /// The result has no summary since the exact minimum and maximum cannot be
+    /// recovered by subtraction. The overflow counts are subtracted.
     pub fn checked_sub(&self, other: &Histogram) -> Result<Histogram, Error> {
         if self.config != other.config {
             return Err(Error::IncompatibleParameters);
         }
 
         let mut result = self.clone();
+        result.summary = None;
+        result.overflow_count = self
+            .overflow_count
+            .checked_sub(other.overflow_count)
+            .ok_or(Error::Underflow)?;
 
         for (this, other) in result.buckets.iter_mut().zip(other.buckets.iter()) {
             *this = this.checked_sub(*other).ok_or(Error::Underflow)?;
@@ -237,12 +493,17 @@ impl Histogram {
     /// as a new histogram.
     ///
     /// An error is returned if the two histograms have incompatible parameters.
+    ///
+    /// The result has no summary since the exact minimum and maximum cannot be
+    /// recovered by subtraction. The overflow counts are subtracted.
     pub fn wrapping_sub(&self, other: &Histogram) -> Result<Histogram, Error> {
         if self.config != other.config {
             return Err(Error::IncompatibleParameters);
         }
 
         let mut result = self.clone();
+        result.summary = None;
+        result.overflow_count = self.overflow_count.wrapping_sub(other.overflow_count);
 
         for (this, other) in result.buckets.iter_mut().zip(other.buckets.iter()) {
             *this = this.wrapping_sub(*other);
@@ -313,6 +574,10 @@ impl std::iter::FusedIterator for Iter<'_> {}
 impl From<&SparseHistogram> for Histogram {
     fn from(other: &SparseHistogram) -> Self {
         let mut histogram = Histogram::with_config(&other.config);
+        histogram.summary = other.summary.map(Box::new);
+        histogram.range_policy = other.range_policy;
+        histogram.overflow_count = other.overflow_count;
+        histogram.counter_policy = other.counter_policy;
 
         for (index, count) in other.index.iter().zip(other.count.iter()) {
             histogram.buckets[*index as usize] = *count;
@@ -330,7 +595,7 @@ mod tests {
     #[cfg(target_pointer_width = "64")]
     #[test]
     fn size() {
-        assert_eq!(std::mem::size_of::<Histogram>(), 48);
+        assert_eq!(std::mem::size_of::<Histogram>(), 72);
     }
 
     #[test]
@@ -549,6 +814,139 @@ mod tests {
         assert_eq!(r.as_slice(), &[2, 2, 2, 2, 2, 2]);
     }
 
+    #[test]
+    // Tests that the summary is maintained and merged
+    fn summary() {
+        let mut histogram = Histogram::new(7, 64).unwrap();
+        let _ = histogram.increment(1);
+        assert_eq!(histogram.summary(), None);
+
+        let mut h1 = Histogram::new(7, 64).unwrap().with_summary();
+        let mut h2 = Histogram::new(7, 64).unwrap().with_summary();
+        for i in 1000..=2000 {
+            let _ = h1.increment(i);
+        }
+        let _ = h2.add(5, 3);
+        assert_eq!(h1.summary().unwrap().min(), Some(1000));
+        assert_eq!(h1.summary().unwrap().max(), Some(2000));
+        assert_eq!(h1.summary().unwrap().mean(), Some(1500.0));
+
+        let merged = h1.checked_add(&h2).unwrap().summary().unwrap();
+        assert_eq!(merged.min(), Some(5));
+        assert_eq!(merged.max(), Some(2000));
+        assert_eq!(merged.count(), 1004);
+        assert_eq!(merged.sum(), 1_501_515);
+
+        assert_eq!(h1.wrapping_add(&h2).unwrap().summary(), Some(merged));
+        assert_eq!(h1.checked_add(&histogram).unwrap().summary(), None);
+        assert_eq!(h1.checked_sub(&h1).unwrap().summary(), None);
+        assert_eq!(h1.downsample(3).unwrap().summary(), h1.summary());
+
+        // a summary count which overflows drops the summary, not the merge
+        let mut h3 = Histogram::new(7, 64).unwrap().with_summary();
+        h3.add(1, 1 << 63).unwrap();
+        let mut h4 = Histogram::new(7, 64).unwrap().with_summary();
+        h4.add(2, 1 << 63).unwrap();
+        let merged = h3.checked_add(&h4).unwrap();
+        assert_eq!(merged.summary(), None);
+        assert_eq!(merged.as_slice()[2], 1 << 63);
+
+        let sparse = SparseHistogram::from(&h1);
+        assert_eq!(sparse.summary(), h1.summary());
+        assert_eq!(Histogram::from(&sparse), h1);
+    }
+
+    #[test]
+    // Tests each range policy for values above the max value
+    fn range_policy() {
+        let mut histogram = Histogram::new(4, 10).unwrap().with_summary();
+        assert_eq!(histogram.range_policy(), RangePolicy::Error);
+        assert_eq!(histogram.increment(1024), Err(Error::OutOfRange));
+        assert_eq!(histogram.summary().unwrap().count(), 0);
+
+        let mut clamped = Histogram::new(4, 10)
+            .unwrap()
+            .with_range_policy(RangePolicy::Clamp);
+        clamped.add(5000, 2).unwrap();
+        assert_eq!(clamped.as_slice().last(), Some(&2));
+        assert_eq!(clamped.overflow_count(), 0);
+
+        let mut counted = Histogram::new(4, 10)
+            .unwrap()
+            .with_summary()
+            .with_range_policy(RangePolicy::Count);
+        counted.increment(5).unwrap();
+        counted.add(5000, 2).unwrap();
+        assert_eq!(counted.overflow_count(), 2);
+        assert_eq!(counted.as_slice().iter().sum::<u64>(), 1);
+        assert_eq!(counted.summary().unwrap().max(), Some(5000));
+        assert_eq!(counted.percentile(1.0).unwrap().unwrap().end(), 5);
+
+        // the overflow count is kept through merges and conversions
+        let merged = counted.checked_add(&counted).unwrap();
+        assert_eq!(merged.overflow_count(), 4);
+        assert_eq!(merged.range_policy(), RangePolicy::Count);
+        assert_eq!(merged.checked_sub(&counted).unwrap().overflow_count(), 2);
+        assert_eq!(counted.checked_sub(&merged), Err(Error::Underflow));
+        assert_eq!(counted.downsample(2).unwrap().overflow_count(), 2);
+
+        let sparse = SparseHistogram::from(&counted);
+        assert_eq!(sparse.overflow_count(), 2);
+        assert_eq!(sparse.range_policy(), RangePolicy::Count);
+        assert_eq!(Histogram::from(&sparse), counted);
+        assert_eq!(
+            sparse.wrapping_add(&sparse).unwrap(),
+            SparseHistogram::from(&merged)
+        );
+    }
+
+    #[test]
+    // Tests each counter policy for a bucket which overflows
+    fn counter_policy() {
+        let mut histogram = Histogram::new(4, 10).unwrap().with_summary();
+        assert_eq!(histogram.counter_policy(), CounterPolicy::Wrapping);
+        histogram.add(5, u64::MAX).unwrap();
+        histogram.add(5, 2).unwrap();
+        assert_eq!(histogram.percentile(0.5).unwrap().unwrap().count(), 1);
+
+        let mut histogram = Histogram::new(4, 10)
+            .unwrap()
+            .with_summary()
+            .with_counter_policy(CounterPolicy::Checked);
+        histogram.add(5, u64::MAX).unwrap();
+        assert_eq!(histogram.increment(5), Err(Error::Overflow));
+        assert_eq!(histogram.summary().unwrap().count(), u64::MAX);
+        assert_eq!(histogram.saturating_add_value(5, 2), Ok(()));
+        assert_eq!(histogram.wrapping_add_value(5, 2), Ok(()));
+        assert_eq!(histogram.percentile(0.5).unwrap().unwrap().count(), 1);
+
+        let mut histogram = Histogram::new(4, 10)
+            .unwrap()
+            .with_counter_policy(CounterPolicy::Saturating);
+        histogram.add(5, u64::MAX).unwrap();
+        histogram.increment(5).unwrap();
+        assert_eq!(
+            histogram.percentile(0.5).unwrap().unwrap().count(),
+            u64::MAX
+        );
+        assert_eq!(histogram.checked_add_value(5, 1), Err(Error::Overflow));
+
+        // the overflow counter follows the same policy
+        let mut histogram = histogram.with_range_policy(RangePolicy::Count);
+        histogram.add(1024, u64::MAX).unwrap();
+        histogram.increment(1024).unwrap();
+        assert_eq!(histogram.overflow_count(), u64::MAX);
+        assert_eq!(
+            histogram.downsample(2).unwrap().counter_policy(),
+            CounterPolicy::Saturating
+        );
+
+        // the policy is kept through a sparse histogram
+        let sparse = SparseHistogram::from(&histogram);
+        assert_eq!(sparse.counter_policy(), CounterPolicy::Saturating);
+        assert_eq!(Histogram::from(&sparse), histogram);
+    }
+
     #[test]
     // Test creating the histogram from buckets
     fn from_buckets() {
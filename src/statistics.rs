@@ -0,0 +1,251 @@
+use crate::{Bucket, Config};
+use core::ops::RangeInclusive;
+
+/// Selects which value within a bucket is used to represent every
+/// observation in that bucket when estimating statistics from the counts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Representative {
+    /// Use the inclusive lower bound of each bucket. Estimates are biased low.
+    Lower,
+    /// Use the midpoint of each bucket. This minimizes the worst-case error.
+    #[default]
+    Midpoint,
+    /// Use the inclusive upper bound of each bucket. Estimates are biased
+    /// high.
+    Upper,
+}
+
+impl Representative {
+    /// Returns the representative value for the bucket.
+    pub(crate) fn value(&self, bucket: &Bucket) -> f64 {
+        match self {
+            Self::Lower => bucket.start() as f64,
+            Self::Midpoint => (bucket.start() as f64 + bucket.end() as f64) / 2.0,
+            Self::Upper => bucket.end() as f64,
+        }
+    }
+}
+
+/// Statistics estimated from the bucket counts of a histogram.
+///
+/// Since only the bucket of each observation is known, every observation is
+/// assumed to have the value selected by the [`Representative`]. The
+/// configured relative error ([`Config::error`]) bounds how far any
+/// representative can be from the value it stands in for, which is used to
+/// provide bounds on the true mean, variance, and standard deviation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statistics {
+    representative: Representative,
+    error: f64,
+    mean: f64,
+    variance: f64,
+    skewness: f64,
+    kurtosis: f64,
+}
+
+impl Statistics {
+    /// Returns the representative used to estimate the statistics.
+    pub fn representative(&self) -> Representative {
+        self.representative
+    }
+
+    /// Returns the estimated arithmetic mean.
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Returns the estimated population variance.
+    pub fn variance(&self) -> f64 {
+        self.variance
+    }
+
+    /// Returns the estimated population standard deviation.
+    pub fn std_dev(&self) -> f64 {
+        self.variance.sqrt()
+    }
+
+    /// Returns the estimated skewness (the standardized third central
+    /// moment). This is `NaN` when the variance is zero.
+    pub fn skewness(&self) -> f64 {
+        self.skewness
+    }
+
+    /// Returns the estimated kurtosis (the standardized fourth central
+    /// moment). A normal distribution has a kurtosis of `3.0`. This is `NaN`
+    /// when the variance is zero.
+    pub fn kurtosis(&self) -> f64 {
+        self.kurtosis
+    }
+
+    /// Returns the estimated excess kurtosis, which is the kurtosis minus
+    /// `3.0`.
+    pub fn excess_kurtosis(&self) -> f64 {
+        self.kurtosis - 3.0
+    }
+
+    /// Returns the range which is guaranteed to contain the true mean of the
+    /// recorded values.
+    pub fn mean_bounds(&self) -> RangeInclusive<f64> {
+        let e = self.error;
+        match self.representative {
+            Representative::Lower => self.mean..=self.mean * (1.0 + e),
+            Representative::Midpoint => self.mean * (1.0 - e / 2.0)..=self.mean * (1.0 + e / 2.0),
+            Representative::Upper => self.mean / (1.0 + e)..=self.mean,
+        }
+    }
+
+    /// Returns the range which is guaranteed to contain the true standard
+    /// deviation of the recorded values.
+    pub fn std_dev_bounds(&self) -> RangeInclusive<f64> {
+        // The standard deviation is a seminorm, so the difference between the
+        // true and estimated values is bounded by the root mean square of the
+        // per-observation error, which is itself bounded relative to the root
+        // mean square of the representatives.
+        let rms = (self.variance + self.mean * self.mean).sqrt();
+        let delta = self.max_relative_error() * rms;
+        let std_dev = self.std_dev();
+
+        (std_dev - delta).max(0.0)..=std_dev + delta
+    }
+
+    /// Returns the range which is guaranteed to contain the true variance of
+    /// the recorded values.
+    pub fn variance_bounds(&self) -> RangeInclusive<f64> {
+        let bounds = self.std_dev_bounds();
+        bounds.start().powi(2)..=bounds.end().powi(2)
+    }
+
+    /// The largest possible difference between a value and its representative
+    /// relative to the representative.
+    fn max_relative_error(&self) -> f64 {
+        match self.representative {
+            Representative::Midpoint => self.error / 2.0,
+            Representative::Lower | Representative::Upper => self.error,
+        }
+    }
+}
+
+/// Estimates the statistics from the buckets of a histogram. Returns `None` if
+/// the histogram is empty.
+pub(crate) fn statistics<'a, T>(
+    histogram: &'a T,
+    config: &Config,
+    representative: Representative,
+) -> Option<Statistics>
+where
+    &'a T: IntoIterator<Item = Bucket>,
+{
+    let mut total = 0.0;
+    let mut sum = 0.0;
+    for bucket in histogram.into_iter().filter(|b| b.count() != 0) {
+        let n = bucket.count() as f64;
+        total += n;
+        sum += n * representative.value(&bucket);
+    }
+
+    if total == 0.0 {
+        return None;
+    }
+
+    let mean = sum / total;
+
+    // a second pass computes the central moments, which is more numerically
+    // stable than deriving them from the raw moments
+    let (mut m2, mut m3, mut m4) = (0.0, 0.0, 0.0);
+    for bucket in histogram.into_iter().filter(|b| b.count() != 0) {
+        let n = bucket.count() as f64;
+        let d = representative.value(&bucket) - mean;
+        let d2 = d * d;
+        m2 += n * d2;
+        m3 += n * d2 * d;
+        m4 += n * d2 * d2;
+    }
+
+    let variance = m2 / total;
+
+    Some(Statistics {
+        representative,
+        error: config.error() / 100.0,
+        mean,
+        variance,
+        skewness: (m3 / total) / variance.powf(1.5),
+        kurtosis: (m4 / total) / (variance * variance),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Histogram, SparseHistogram};
+    use rand::{RngExt, SeedableRng};
+
+    #[test]
+    fn empty() {
+        let histogram = Histogram::new(7, 64).unwrap();
+        assert_eq!(histogram.statistics(Representative::Midpoint), None);
+        let histogram = SparseHistogram::new(7, 64).unwrap();
+        assert_eq!(histogram.statistics(Representative::Midpoint), None);
+    }
+
+    #[test]
+    // Values in the linear region are exact for every representative
+    fn exact() {
+        let mut histogram = Histogram::new(7, 64).unwrap();
+        for value in 1..=100 {
+            let _ = histogram.increment(value);
+        }
+
+        for representative in [
+            Representative::Lower,
+            Representative::Midpoint,
+            Representative::Upper,
+        ] {
+            let stats = histogram.statistics(representative).unwrap();
+            assert_eq!(stats.mean(), 50.5);
+            assert_eq!(stats.variance(), 833.25);
+            assert!(stats.skewness().abs() < 1e-12);
+            assert!((stats.kurtosis() - 1.7997).abs() < 1e-4);
+        }
+
+        let sparse = SparseHistogram::from(&histogram);
+        assert_eq!(
+            sparse.statistics(Representative::Midpoint),
+            histogram.statistics(Representative::Midpoint)
+        );
+
+        let mut constant = Histogram::new(7, 64).unwrap();
+        let _ = constant.add(42, 10);
+        let stats = constant.statistics(Representative::Midpoint).unwrap();
+        assert_eq!(stats.mean(), 42.0);
+        assert_eq!(stats.std_dev(), 0.0);
+        assert!(stats.skewness().is_nan());
+    }
+
+    #[test]
+    // The true statistics lie within the reported bounds
+    fn bounds() {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(7);
+        let mut histogram = Histogram::new(4, 32).unwrap();
+        let mut values = Vec::new();
+        for _ in 0..10_000 {
+            let v: u64 = rng.random_range(1_000..1_000_000);
+            values.push(v as f64);
+            let _ = histogram.increment(v);
+        }
+
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let std_dev = (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n).sqrt();
+
+        for representative in [
+            Representative::Lower,
+            Representative::Midpoint,
+            Representative::Upper,
+        ] {
+            let stats = histogram.statistics(representative).unwrap();
+            assert!(stats.mean_bounds().contains(&mean));
+            assert!(stats.std_dev_bounds().contains(&std_dev));
+            assert!(stats.variance_bounds().contains(&(std_dev * std_dev)));
+        }
+    }
+}
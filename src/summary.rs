@@ -0,0 +1,185 @@
+use crate::CounterPolicy;
+
+/// Exact summary statistics for the values recorded into a histogram.
+///
+/// Unlike the buckets, which only record which range a value fell into, the
+/// summary tracks the exact minimum and maximum values as well as the sum and
+/// the number of observations. The sum is kept in a `u128`, so it is exact as
+/// long as the count does not overflow. The count uses wrapping arithmetic on
+/// overflow when values are recorded, matching the bucket counters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Summary {
+    pub(crate) min: u64,
+    pub(crate) max: u64,
+    pub(crate) sum: u128,
+    pub(crate) count: u64,
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Summary {
+    /// Creates an empty summary.
+    pub(crate) const fn new() -> Self {
+        Self {
+            min: u64::MAX,
+            max: 0,
+            sum: 0,
+            count: 0,
+        }
+    }
+
+    /// Returns the smallest recorded value, or `None` if nothing has been
+    /// recorded.
+    pub fn min(&self) -> Option<u64> {
+        (self.count != 0).then_some(self.min)
+    }
+
+    /// Returns the largest recorded value, or `None` if nothing has been
+    /// recorded.
+    pub fn max(&self) -> Option<u64> {
+        (self.count != 0).then_some(self.max)
+    }
+
+    /// Returns the sum of all recorded values.
+    pub fn sum(&self) -> u128 {
+        self.sum
+    }
+
+    /// Returns the number of recorded values.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the exact arithmetic mean of the recorded values, or `None` if
+    /// nothing has been recorded.
+    pub fn mean(&self) -> Option<f64> {
+        (self.count != 0).then(|| self.sum as f64 / self.count as f64)
+    }
+
+    /// Records `count` observations of `value`.
+    pub(crate) fn record(&mut self, value: u64, count: u64) {
+        if count == 0 {
+            return;
+        }
+
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum = self.sum.wrapping_add(value as u128 * count as u128);
+        self.count = self.count.wrapping_add(count);
+    }
+
+    /// Merges two summaries, returning `None` if the sum or count overflow.
+    pub(crate) fn checked_add(&self, other: &Summary) -> Option<Summary> {
+        Some(Summary {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+            sum: self.sum.checked_add(other.sum)?,
+            count: self.count.checked_add(other.count)?,
+        })
+    }
+
+    /// Merges two summaries, using wrapping arithmetic for the sum and count.
+    pub(crate) fn wrapping_add(&self, other: &Summary) -> Summary {
+        Summary {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+            sum: self.sum.wrapping_add(other.sum),
+            count: self.count.wrapping_add(other.count),
+        }
+    }
+}
+
+/// Merges two optional summaries as [`checked_merge`] does for
+/// [`CounterPolicy::Checked`], and as [`wrapping_merge`] does otherwise.
+pub(crate) fn merge(
+    policy: CounterPolicy,
+    a: Option<Summary>,
+    b: Option<Summary>,
+) -> Option<Summary> {
+    match policy {
+        CounterPolicy::Checked => checked_merge(a, b),
+        _ => wrapping_merge(a, b),
+    }
+}
+
+/// Merges two optional summaries. The result only has a summary if both of
+/// the inputs have one and the merged count does not overflow, since
+/// otherwise the exact values are unknown.
+pub(crate) fn checked_merge(a: Option<Summary>, b: Option<Summary>) -> Option<Summary> {
+    match (a, b) {
+        (Some(a), Some(b)) => a.checked_add(&b),
+        _ => None,
+    }
+}
+
+/// Merges two optional summaries using wrapping arithmetic. See
+/// [`checked_merge`].
+pub(crate) fn wrapping_merge(a: Option<Summary>, b: Option<Summary>) -> Option<Summary> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.wrapping_add(&b)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record() {
+        let mut summary = Summary::new();
+        assert_eq!(summary.min(), None);
+        assert_eq!(summary.max(), None);
+        assert_eq!(summary.mean(), None);
+
+        summary.record(10, 2);
+        summary.record(4, 1);
+        summary.record(100, 0);
+
+        assert_eq!(summary.min(), Some(4));
+        assert_eq!(summary.max(), Some(10));
+        assert_eq!(summary.sum(), 24);
+        assert_eq!(summary.count(), 3);
+        assert_eq!(summary.mean(), Some(8.0));
+
+        // the sum is exact even when it does not fit in a u64
+        summary.record(u64::MAX, 2);
+        assert_eq!(summary.sum(), 24 + 2 * u64::MAX as u128);
+    }
+
+    #[test]
+    fn merge() {
+        let mut a = Summary::new();
+        a.record(5, 1);
+        let mut b = Summary::new();
+        b.record(1, 1);
+        b.record(9, 1);
+
+        let merged = a.checked_add(&b).unwrap();
+        assert_eq!(merged.min(), Some(1));
+        assert_eq!(merged.max(), Some(9));
+        assert_eq!(merged.sum(), 15);
+        assert_eq!(merged.count(), 3);
+
+        // merging with an empty summary is a no-op
+        assert_eq!(a.checked_add(&Summary::new()), Some(a));
+
+        let mut c = Summary::new();
+        c.record(u64::MAX, 1);
+        assert_eq!(a.checked_add(&c).unwrap().sum(), 5 + u64::MAX as u128);
+
+        let mut d = Summary::new();
+        d.record(1, u64::MAX);
+        assert_eq!(a.checked_add(&d), None);
+        assert_eq!(a.wrapping_add(&d).count(), 0);
+
+        assert_eq!(checked_merge(Some(a), None), None);
+        assert_eq!(wrapping_merge(None, Some(b)), None);
+    }
+}
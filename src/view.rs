@@ -0,0 +1,385 @@
+use crate::binary::{self, Header};
+use crate::bucket;
+use crate::interpolation::{self, Interpolation};
+use crate::rank::{self, Rank};
+use crate::statistics::{self, Representative, Statistics};
+use crate::{Bucket, Config, CounterPolicy, Error, RangePolicy, SparseHistogram, Summary};
+
+/// A read-only view of a [`SparseHistogram`] in the binary format produced
+/// by [`SparseHistogram::to_bytes`], which is queried without decoding the
+/// buckets into vectors.
+///
+/// The bytes are validated when the view is created, with the same checks as
+/// [`SparseHistogram::from_bytes`]. Queries then decode the buckets as they
+/// scan them, so a view can be created and queried for each record of a
+/// memory-mapped file without allocating.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SparseHistogramView<'a> {
+    config: Config,
+    len: usize,
+    index: &'a [u8],
+    count: &'a [u8],
+    total_count: u128,
+    summary: Option<Summary>,
+    range_policy: RangePolicy,
+    overflow_count: u64,
+    counter_policy: CounterPolicy,
+}
+
+impl<'a> SparseHistogramView<'a> {
+    /// Creates a view over a histogram in the binary format produced by
+    /// [`SparseHistogram::to_bytes`].
+    ///
+    /// Returns the same errors as [`SparseHistogram::from_bytes`].
+    pub fn new(bytes: &'a [u8]) -> Result<Self, Error> {
+        let mut buf = bytes;
+        let Header {
+            config,
+            len,
+            summary,
+            range,
+            counter,
+        } = binary::read_header(&mut buf).map_err(binary::to_error)?;
+
+        let index = buf;
+        let mut next = 0;
+        for _ in 0..len {
+            let i = binary::read_index(&mut buf, next).map_err(binary::to_error)?;
+            if i as usize >= config.total_buckets() {
+                return Err(Error::OutOfRange);
+            }
+            next = i as u64 + 1;
+        }
+        let index = &index[..index.len() - buf.len()];
+
+        let count = buf;
+        let mut total_count = 0;
+        for _ in 0..len {
+            let c = binary::read_varint(&mut buf).map_err(binary::to_error)?;
+            if c == 0 {
+                return Err(Error::IncompatibleParameters);
+            }
+            total_count += c as u128;
+        }
+        let count = &count[..count.len() - buf.len()];
+
+        let summary = if summary {
+            Some(binary::read_summary(&mut buf).map_err(binary::to_error)?)
+        } else {
+            None
+        };
+
+        let (range_policy, overflow_count) = if range {
+            binary::read_range(&mut buf).map_err(binary::to_error)?
+        } else {
+            (RangePolicy::Error, 0)
+        };
+        let counter_policy = if counter {
+            binary::read_counter(&mut buf).map_err(binary::to_error)?
+        } else {
+            CounterPolicy::Wrapping
+        };
+
+        if !buf.is_empty() {
+            return Err(Error::InvalidEncoding);
+        }
+
+        Ok(Self {
+            config,
+            len,
+            index,
+            count,
+            total_count,
+            summary,
+            range_policy,
+            overflow_count,
+            counter_policy,
+        })
+    }
+
+    /// Returns the bucket configuration.
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Returns the exact summary, if the histogram was encoded with one.
+    pub fn summary(&self) -> Option<Summary> {
+        self.summary
+    }
+
+    /// Returns the range policy the histogram was encoded with.
+    pub fn range_policy(&self) -> RangePolicy {
+        self.range_policy
+    }
+
+    /// Returns the counter policy the histogram was encoded with.
+    pub fn counter_policy(&self) -> CounterPolicy {
+        self.counter_policy
+    }
+
+    /// Returns the count of values above the max value of the config. See
+    /// [`Histogram::overflow_count`](crate::Histogram::overflow_count).
+    pub fn overflow_count(&self) -> u64 {
+        self.overflow_count
+    }
+
+    /// Returns the number of non-zero buckets.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if there are no non-zero buckets.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the total number of observations.
+    pub fn total_count(&self) -> u128 {
+        self.total_count
+    }
+
+    /// Return a collection of percentiles from this histogram.
+    ///
+    /// Each percentile should be in the inclusive range `0.0..=1.0`. For
+    /// example, the 50th percentile (median) can be found using `0.5`.
+    ///
+    /// The results will be sorted by the percentile.
+    pub fn percentiles(&self, percentiles: &[f64]) -> Result<Option<Vec<(f64, Bucket)>>, Error> {
+        bucket::percentiles(self.iter(), self.total_count, percentiles)
+    }
+
+    /// Return a single percentile from this histogram.
+    ///
+    /// The percentile should be in the inclusive range `0.0..=1.0`. For
+    /// example, the 50th percentile (median) can be found using `0.5`.
+    pub fn percentile(&self, percentile: f64) -> Result<Option<Bucket>, Error> {
+        self.percentiles(&[percentile])
+            .map(|v| v.map(|x| x.first().unwrap().1.clone()))
+    }
+
+    /// Return a collection of percentiles from this histogram, each estimated
+    /// as a single value from its bucket according to the `interpolation`.
+    ///
+    /// The results will be sorted by the percentile.
+    pub fn percentile_values(
+        &self,
+        percentiles: &[f64],
+        interpolation: Interpolation,
+    ) -> Result<Option<Vec<(f64, f64)>>, Error> {
+        interpolation::percentile_values(self, percentiles, interpolation)
+    }
+
+    /// Return a single percentile from this histogram, estimated as a single
+    /// value from its bucket according to the `interpolation`.
+    pub fn percentile_value(
+        &self,
+        percentile: f64,
+        interpolation: Interpolation,
+    ) -> Result<Option<f64>, Error> {
+        self.percentile_values(&[percentile], interpolation)
+            .map(|v| v.map(|x| x.first().unwrap().1))
+    }
+
+    /// Returns the fraction of observations which are less than or equal to
+    /// the value, or `None` if the histogram is empty. See
+    /// [`SparseHistogram::rank`] for details.
+    pub fn rank(&self, value: u64) -> Result<Option<Rank>, Error> {
+        self.ranks(&[value])
+            .map(|v| v.map(|x| x.first().unwrap().1))
+    }
+
+    /// Return the ranks of a collection of values from this histogram. See
+    /// [`rank`](Self::rank) for details.
+    ///
+    /// The results will be sorted by the value.
+    pub fn ranks(&self, values: &[u64]) -> Result<Option<Vec<(u64, Rank)>>, Error> {
+        rank::ranks(self, &self.config, values)
+    }
+
+    /// Returns statistics estimated from the bucket counts, or `None` if the
+    /// histogram is empty. See [`Statistics`] for details about the accuracy
+    /// of the estimates.
+    pub fn statistics(&self, representative: Representative) -> Option<Statistics> {
+        statistics::statistics(self, &self.config, representative)
+    }
+
+    /// Returns an iterator across the non-zero histogram buckets.
+    pub fn iter(&self) -> Iter<'a> {
+        Iter {
+            config: self.config,
+            remaining: self.len,
+            next: 0,
+            index: self.index,
+            count: self.count,
+        }
+    }
+}
+
+impl<'a> IntoIterator for &SparseHistogramView<'a> {
+    type Item = Bucket;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl From<&SparseHistogramView<'_>> for SparseHistogram {
+    fn from(view: &SparseHistogramView<'_>) -> Self {
+        // every count takes at least one byte, so the bytes of the view bound
+        // the space reserved
+        let capacity = view.len.min(view.count.len());
+        let mut index = Vec::with_capacity(capacity);
+        let mut count = Vec::with_capacity(capacity);
+
+        let mut buckets = view.iter();
+        while let Some((i, c)) = buckets.next_raw() {
+            index.push(i);
+            count.push(c);
+        }
+
+        Self {
+            config: view.config,
+            index,
+            count,
+            summary: view.summary,
+            range_policy: view.range_policy,
+            overflow_count: view.overflow_count,
+            counter_policy: view.counter_policy,
+        }
+    }
+}
+
+/// An iterator across the buckets of a [`SparseHistogramView`].
+pub struct Iter<'a> {
+    config: Config,
+    remaining: usize,
+    next: u64,
+    index: &'a [u8],
+    count: &'a [u8],
+}
+
+impl Iter<'_> {
+    /// Returns the next bucket index and count.
+    fn next_raw(&mut self) -> Option<(u32, u64)> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        // the bytes were validated when the view was created
+        let index = binary::read_index(&mut self.index, self.next).unwrap();
+        let count = binary::read_varint(&mut self.count).unwrap();
+
+        self.next = index as u64 + 1;
+        self.remaining -= 1;
+
+        Some((index, count))
+    }
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Bucket;
+
+    fn next(&mut self) -> Option<<Self as std::iter::Iterator>::Item> {
+        self.next_raw().map(|(index, count)| Bucket {
+            count,
+            range: self.config.index_to_range(index as usize),
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {
+    fn len(&self) -> usize {
+        self.remaining
+    }
+}
+
+impl std::iter::FusedIterator for Iter<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Histogram;
+
+    fn histogram() -> Histogram {
+        let mut histogram = Histogram::new(7, 64).unwrap().with_summary();
+        for value in 1..=1000 {
+            histogram.add(value * value, value).unwrap();
+        }
+        histogram
+    }
+
+    #[test]
+    fn queries() {
+        let histogram = histogram();
+        let sparse = SparseHistogram::from(&histogram);
+        let bytes = sparse.to_bytes();
+        let view = SparseHistogramView::new(&bytes).unwrap();
+
+        assert_eq!(view.config(), histogram.config());
+        assert_eq!(view.summary(), histogram.summary());
+        assert_eq!(view.len(), sparse.index().len());
+        assert_eq!(view.total_count(), 500_500);
+        assert!(view.iter().eq(sparse.iter()));
+        assert_eq!(view.iter().len(), view.len());
+
+        let percentiles = [0.0, 0.25, 0.5, 0.9, 0.99, 0.999, 1.0];
+        assert_eq!(
+            view.percentiles(&percentiles),
+            histogram.percentiles(&percentiles)
+        );
+        assert_eq!(
+            view.percentile_values(&percentiles, Interpolation::Linear),
+            histogram.percentile_values(&percentiles, Interpolation::Linear)
+        );
+        assert_eq!(view.percentile(2.0), Err(Error::InvalidPercentile));
+
+        let values = [0, 1, 5000, 250_000, 1_000_000, u64::MAX];
+        assert_eq!(view.ranks(&values), histogram.ranks(&values));
+        assert_eq!(
+            view.statistics(Representative::Midpoint),
+            histogram.statistics(Representative::Midpoint)
+        );
+
+        assert_eq!(SparseHistogram::from(&view), sparse);
+    }
+
+    #[test]
+    fn empty() {
+        let bytes = SparseHistogram::new(7, 64).unwrap().to_bytes();
+        let view = SparseHistogramView::new(&bytes).unwrap();
+
+        assert!(view.is_empty());
+        assert_eq!(view.iter().next(), None);
+        assert_eq!(view.percentile(0.5), Ok(None));
+        assert_eq!(view.rank(42), Ok(None));
+    }
+
+    #[test]
+    fn invalid() {
+        let bytes = SparseHistogram::from(&histogram()).to_bytes();
+
+        // the same errors as decoding
+        for len in [0, 8, bytes.len() - 1] {
+            assert_eq!(
+                SparseHistogramView::new(&bytes[..len]),
+                Err(Error::InvalidEncoding)
+            );
+        }
+
+        let out_of_range = [b'H', b'I', b'S', b'T', 1, 2, 4, 0, 1, 12, 1];
+        assert_eq!(
+            SparseHistogramView::new(&out_of_range),
+            Err(Error::OutOfRange)
+        );
+        let zero_count = [b'H', b'I', b'S', b'T', 1, 2, 4, 0, 1, 3, 0];
+        assert_eq!(
+            SparseHistogramView::new(&zero_count),
+            Err(Error::IncompatibleParameters)
+        );
+    }
+}
@@ -0,0 +1,347 @@
+use crate::{Bucket, Config, Error, Histogram};
+use std::time::{Duration, Instant};
+
+/// A histogram which covers a sliding window made up of a fixed number of
+/// intervals.
+///
+/// Values are recorded into the current interval. Each rotation starts a new
+/// interval and discards the oldest one. Rotations are either driven
+/// explicitly with [`tick`](WindowedHistogram::tick) or by a clock with
+/// [`advance_to`](WindowedHistogram::advance_to).
+///
+/// A running aggregate of all the intervals is maintained incrementally by
+/// adding new values to it and subtracting the oldest interval on rotation,
+/// so percentile queries over the whole window do not need to merge the
+/// intervals.
+#[derive(Clone, Debug)]
+pub struct WindowedHistogram {
+    config: Config,
+    slots: Box<[Histogram]>,
+    current: usize,
+    aggregate: Histogram,
+    clock: Option<Clock>,
+}
+
+/// Tracks when a clock-driven window should next rotate.
+#[derive(Clone, Copy, Debug)]
+struct Clock {
+    interval: Duration,
+    next: Instant,
+}
+
+impl WindowedHistogram {
+    /// Construct a new windowed histogram from the provided parameters and
+    /// number of intervals. See the documentation for [`crate::Config`] to
+    /// understand their meaning.
+    ///
+    /// Returns `Err(Error::IncompatibleParameters)` if `intervals` is zero.
+    pub fn new(grouping_power: u8, max_value_power: u8, intervals: usize) -> Result<Self, Error> {
+        let config = Config::new(grouping_power, max_value_power)?;
+
+        Self::with_config(&config, intervals)
+    }
+
+    /// Creates a new windowed histogram with the provided number of intervals
+    /// using a provided [`crate::Config`].
+    ///
+    /// Returns `Err(Error::IncompatibleParameters)` if `intervals` is zero.
+    pub fn with_config(config: &Config, intervals: usize) -> Result<Self, Error> {
+        if intervals == 0 {
+            return Err(Error::IncompatibleParameters);
+        }
+
+        Ok(Self {
+            config: *config,
+            slots: vec![Histogram::with_config(config); intervals].into(),
+            current: 0,
+            aggregate: Histogram::with_config(config),
+            clock: None,
+        })
+    }
+
+    /// Drives rotation from a clock, with each interval covering `interval`
+    /// of time beginning at `start`. See
+    /// [`advance_to`](WindowedHistogram::advance_to).
+    ///
+    /// Returns `Err(Error::IncompatibleParameters)` if `interval` is zero.
+    pub fn with_interval(mut self, interval: Duration, start: Instant) -> Result<Self, Error> {
+        if interval.is_zero() {
+            return Err(Error::IncompatibleParameters);
+        }
+
+        self.clock = Some(Clock {
+            interval,
+            next: start + interval,
+        });
+        Ok(self)
+    }
+
+    /// Returns the bucket configuration of the histogram.
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Returns the number of intervals covered by the window.
+    pub fn intervals(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Increment the counter for the bucket corresponding to the provided
+    /// value in the current interval by one.
+    pub fn increment(&mut self, value: u64) -> Result<(), Error> {
+        self.add(value, 1)
+    }
+
+    /// Add some count to the counter for the bucket corresponding to the
+    /// provided value in the current interval. The counter uses wrapping
+    /// arithmetic on overflow.
+    pub fn add(&mut self, value: u64, count: u64) -> Result<(), Error> {
+        let index = self.config.value_to_index(value)?;
+        let slot = &mut self.slots[self.current].buckets[index];
+        *slot = slot.wrapping_add(count);
+        let total = &mut self.aggregate.buckets[index];
+        *total = total.wrapping_add(count);
+        Ok(())
+    }
+
+    /// Adds all the counts of the other histogram to the current interval.
+    /// The counters use wrapping arithmetic on overflow.
+    ///
+    /// Returns `Err(Error::IncompatibleParameters)` if the configs don't
+    /// match.
+    pub fn add_histogram(&mut self, other: &Histogram) -> Result<(), Error> {
+        if self.config != other.config {
+            return Err(Error::IncompatibleParameters);
+        }
+
+        let slot = &mut self.slots[self.current];
+        for ((this, total), other) in slot
+            .buckets
+            .iter_mut()
+            .zip(self.aggregate.buckets.iter_mut())
+            .zip(other.buckets.iter())
+        {
+            *this = this.wrapping_add(*other);
+            *total = total.wrapping_add(*other);
+        }
+
+        Ok(())
+    }
+
+    /// Starts a new interval, discarding the oldest interval in the window.
+    pub fn tick(&mut self) {
+        self.current = (self.current + 1) % self.slots.len();
+
+        let slot = &mut self.slots[self.current];
+        for (this, total) in slot
+            .buckets
+            .iter_mut()
+            .zip(self.aggregate.buckets.iter_mut())
+        {
+            *total = total.wrapping_sub(*this);
+            *this = 0;
+        }
+    }
+
+    /// Rotates the window once for every interval which has fully elapsed
+    /// by `now`. This has no effect unless the window was created with
+    /// [`with_interval`](WindowedHistogram::with_interval).
+    pub fn advance_to(&mut self, now: Instant) {
+        let Some(clock) = &mut self.clock else {
+            return;
+        };
+
+        if now < clock.next {
+            return;
+        }
+
+        let interval = clock.interval.as_nanos();
+        let since = now.duration_since(clock.next).as_nanos();
+        let ticks = since / interval + 1;
+        clock.next = now - Duration::from_nanos((since % interval) as u64) + clock.interval;
+
+        // rotating through every interval discards the whole window
+        if ticks >= self.slots.len() as u128 {
+            for slot in self.slots.iter_mut() {
+                slot.buckets.fill(0);
+            }
+            self.aggregate.buckets.fill(0);
+            self.current =
+                (self.current + (ticks % self.slots.len() as u128) as usize) % self.slots.len();
+        } else {
+            for _ in 0..ticks {
+                self.tick();
+            }
+        }
+    }
+
+    /// Returns a histogram covering the whole window.
+    pub fn aggregate(&self) -> &Histogram {
+        &self.aggregate
+    }
+
+    /// Returns a histogram covering the most recent `intervals` intervals of
+    /// the window, including the current interval.
+    ///
+    /// Returns `Err(Error::IncompatibleParameters)` if `intervals` is zero or
+    /// larger than the number of intervals in the window.
+    pub fn recent(&self, intervals: usize) -> Result<Histogram, Error> {
+        if intervals == 0 || intervals > self.slots.len() {
+            return Err(Error::IncompatibleParameters);
+        }
+
+        if intervals == self.slots.len() {
+            return Ok(self.aggregate.clone());
+        }
+
+        let mut histogram = self.slots[self.current].clone();
+        for age in 1..intervals {
+            let slot = (self.current + self.slots.len() - age) % self.slots.len();
+            histogram = histogram.wrapping_add(&self.slots[slot])?;
+        }
+
+        Ok(histogram)
+    }
+
+    /// Return a collection of percentiles over the whole window.
+    ///
+    /// Each percentile should be in the inclusive range `0.0..=1.0`. For
+    /// example, the 50th percentile (median) can be found using `0.5`.
+    ///
+    /// The results will be sorted by the percentile.
+    pub fn percentiles(&self, percentiles: &[f64]) -> Result<Option<Vec<(f64, Bucket)>>, Error> {
+        self.aggregate.percentiles(percentiles)
+    }
+
+    /// Return a single percentile over the whole window.
+    ///
+    /// The percentile should be in the inclusive range `0.0..=1.0`. For
+    /// example, the 50th percentile (median) can be found using `0.5`.
+    pub fn percentile(&self, percentile: f64) -> Result<Option<Bucket>, Error> {
+        self.aggregate.percentile(percentile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid() {
+        assert_eq!(
+            WindowedHistogram::new(7, 64, 0).map(|_| ()),
+            Err(Error::IncompatibleParameters)
+        );
+        assert_eq!(
+            WindowedHistogram::new(7, 64, 3)
+                .unwrap()
+                .with_interval(Duration::ZERO, Instant::now())
+                .map(|_| ()),
+            Err(Error::IncompatibleParameters)
+        );
+    }
+
+    #[test]
+    // Tests that old intervals are discarded as the window rotates
+    fn tick() {
+        let mut window = WindowedHistogram::new(7, 64, 3).unwrap();
+        window.increment(1).unwrap();
+        window.tick();
+        window.increment(2).unwrap();
+        window.tick();
+        window.increment(3).unwrap();
+
+        assert_eq!(window.percentile(0.0).map(|b| b.unwrap().end()), Ok(1));
+        assert_eq!(window.percentile(1.0).map(|b| b.unwrap().end()), Ok(3));
+
+        window.tick();
+        assert_eq!(window.percentile(0.0).map(|b| b.unwrap().end()), Ok(2));
+        assert_eq!(window.percentile(1.0).map(|b| b.unwrap().end()), Ok(3));
+
+        window.tick();
+        window.tick();
+        assert_eq!(window.percentile(0.5), Ok(None));
+        assert!(window.aggregate().as_slice().iter().all(|c| *c == 0));
+    }
+
+    #[test]
+    // Tests queries over the most recent intervals
+    fn recent() {
+        let mut window = WindowedHistogram::new(7, 64, 4).unwrap();
+        for value in 1..=4 {
+            window.increment(value * 10).unwrap();
+            window.tick();
+        }
+        window.increment(50).unwrap();
+
+        let h = window.recent(1).unwrap();
+        assert_eq!(h.percentile(0.0).map(|b| b.unwrap().end()), Ok(50));
+
+        let h = window.recent(3).unwrap();
+        assert_eq!(h.percentile(0.0).map(|b| b.unwrap().end()), Ok(30));
+
+        assert_eq!(&window.recent(4).unwrap(), window.aggregate());
+        assert_eq!(window.recent(0), Err(Error::IncompatibleParameters));
+        assert_eq!(window.recent(5), Err(Error::IncompatibleParameters));
+
+        let mut other = Histogram::new(7, 64).unwrap();
+        other.increment(5).unwrap();
+        window.add_histogram(&other).unwrap();
+        let h = window.recent(1).unwrap();
+        assert_eq!(h.percentile(0.0).map(|b| b.unwrap().end()), Ok(5));
+
+        let other = Histogram::new(6, 64).unwrap();
+        assert_eq!(
+            window.add_histogram(&other),
+            Err(Error::IncompatibleParameters)
+        );
+    }
+
+    #[test]
+    // Tests rotation driven by a clock
+    fn advance_to() {
+        let start = Instant::now();
+        let interval = Duration::from_secs(1);
+        let mut window = WindowedHistogram::new(7, 64, 3)
+            .unwrap()
+            .with_interval(interval, start)
+            .unwrap();
+
+        window.increment(1).unwrap();
+        window.advance_to(start + Duration::from_millis(500));
+        window.increment(2).unwrap();
+        assert_eq!(
+            window
+                .recent(1)
+                .unwrap()
+                .percentile(0.0)
+                .unwrap()
+                .unwrap()
+                .end(),
+            1
+        );
+
+        window.advance_to(start + Duration::from_millis(2500));
+        window.increment(3).unwrap();
+        assert_eq!(
+            window
+                .recent(1)
+                .unwrap()
+                .percentile(0.0)
+                .unwrap()
+                .unwrap()
+                .end(),
+            3
+        );
+        assert_eq!(window.percentile(0.0).map(|b| b.unwrap().end()), Ok(1));
+
+        window.advance_to(start + Duration::from_secs(3));
+        assert_eq!(window.percentile(0.0).map(|b| b.unwrap().end()), Ok(3));
+
+        window.advance_to(start + Duration::from_secs(60));
+        assert_eq!(window.percentile(0.5), Ok(None));
+        window.increment(4).unwrap();
+        window.advance_to(start + Duration::from_millis(60_999));
+        assert_eq!(window.percentile(0.0).map(|b| b.unwrap().end()), Ok(4));
+    }
+}